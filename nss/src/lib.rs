@@ -0,0 +1,461 @@
+//! A glibc NSS module answering host lookups from hostie's store.
+//!
+//! Built as `libnss_hostie.so`, installed as `libnss_hostie.so.2` next to the
+//! other NSS modules and enabled by adding `hostie` to the `hosts:` line of
+//! `/etc/nsswitch.conf`. Lookups are answered from the calling user's store,
+//! the hosts-format file `hostie --store` manages, so adding and removing
+//! names needs no root. Expired entries are ignored, and entries such as
+//! `127.0.0.1 *.myapp.test` match every subdomain.
+
+#![cfg(target_os = "linux")]
+
+use std::ffi::{CStr, c_char, c_int, c_void};
+use std::net::IpAddr;
+use std::ptr;
+use std::time::SystemTime;
+
+use hostie::hosts::HostsFile;
+use hostie::paths::store_path;
+use libc::{AF_INET, AF_INET6, hostent, socklen_t};
+
+type NssStatus = c_int;
+const NSS_STATUS_TRYAGAIN: NssStatus = -2;
+const NSS_STATUS_UNAVAIL: NssStatus = -1;
+const NSS_STATUS_NOTFOUND: NssStatus = 0;
+const NSS_STATUS_SUCCESS: NssStatus = 1;
+
+// From <netdb.h>
+const NETDB_INTERNAL: c_int = -1;
+const HOST_NOT_FOUND: c_int = 1;
+
+/// glibc's `struct gaih_addrtuple`, the result of `gethostbyname4_r`.
+#[repr(C)]
+pub struct GaihAddrtuple {
+    pub next: *mut GaihAddrtuple,
+    pub name: *mut c_char,
+    pub family: c_int,
+    pub addr: [u32; 4],
+    pub scopeid: u32,
+}
+
+/// Addresses for `name`: those of its entries, or failing that those of the
+/// most specific wildcard entry matching it.
+pub fn resolve(hosts: &HostsFile, name: &str, now: SystemTime) -> Vec<IpAddr> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let mut exact = Vec::new();
+    let mut wildcard: Vec<IpAddr> = Vec::new();
+    let mut wildcard_len = 0;
+
+    for entry in hosts.entries().filter(|entry| !entry.is_expired(now)) {
+        let Ok(ip) = entry.ip.parse::<IpAddr>() else {
+            continue;
+        };
+        for hostname in &entry.hostnames {
+            let hostname = hostname.to_ascii_lowercase();
+            match hostname.strip_prefix('*') {
+                Some(suffix) if name.len() > suffix.len() && name.ends_with(suffix) => {
+                    if suffix.len() > wildcard_len {
+                        wildcard_len = suffix.len();
+                        wildcard.clear();
+                    }
+                    if suffix.len() == wildcard_len && !wildcard.contains(&ip) {
+                        wildcard.push(ip);
+                    }
+                }
+                Some(_) => {}
+                None if hostname == name && !exact.contains(&ip) => exact.push(ip),
+                None => {}
+            }
+        }
+    }
+
+    if exact.is_empty() { wildcard } else { exact }
+}
+
+/// Hostnames pointing at `ip`, in file order. Wildcards have no name to give.
+pub fn reverse(hosts: &HostsFile, ip: IpAddr, now: SystemTime) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for entry in hosts.entries().filter(|entry| !entry.is_expired(now)) {
+        if entry.ip.parse::<IpAddr>() != Ok(ip) {
+            continue;
+        }
+        for hostname in &entry.hostnames {
+            if !hostname.starts_with('*') && !names.contains(hostname) {
+                names.push(hostname.clone());
+            }
+        }
+    }
+    names
+}
+
+/// The store of the calling user, or `None` where it must not be trusted.
+fn read_store() -> Option<HostsFile> {
+    // Like glibc with HOSTALIASES, setuid programs ignore user-controlled files
+    if unsafe { libc::getauxval(libc::AT_SECURE) } != 0 {
+        return None;
+    }
+
+    let path = store_path();
+    if !path.is_absolute() {
+        return None;
+    }
+    HostsFile::read(path).ok()
+}
+
+/// Carves the results out of the buffer the caller provides.
+struct Buffer {
+    ptr: *mut u8,
+    len: usize,
+    used: usize,
+}
+
+impl Buffer {
+    fn new(ptr: *mut c_char, len: usize) -> Self {
+        Buffer {
+            ptr: ptr.cast(),
+            len,
+            used: 0,
+        }
+    }
+
+    fn alloc<T>(&mut self, count: usize) -> Option<*mut T> {
+        let base = self.ptr as usize;
+        let start = (base + self.used).next_multiple_of(align_of::<T>()) - base;
+        let end = start.checked_add(size_of::<T>().checked_mul(count)?)?;
+        if end > self.len {
+            return None;
+        }
+        self.used = end;
+        Some(unsafe { self.ptr.add(start) }.cast())
+    }
+
+    fn alloc_str(&mut self, s: &str) -> Option<*mut c_char> {
+        let ptr = self.alloc::<u8>(s.len() + 1)?;
+        unsafe {
+            ptr::copy_nonoverlapping(s.as_ptr(), ptr, s.len());
+            *ptr.add(s.len()) = 0;
+        }
+        Some(ptr.cast())
+    }
+}
+
+/// Fills `host` with `name`, `aliases` and those of `addresses` in `family`.
+fn fill_hostent(
+    buffer: &mut Buffer,
+    host: &mut hostent,
+    name: &str,
+    aliases: &[String],
+    family: c_int,
+    addresses: &[IpAddr],
+) -> Option<()> {
+    let octets: Vec<Vec<u8>> = addresses
+        .iter()
+        .filter_map(|ip| match (ip, family) {
+            (IpAddr::V4(ip), AF_INET) => Some(ip.octets().to_vec()),
+            (IpAddr::V6(ip), AF_INET6) => Some(ip.octets().to_vec()),
+            _ => None,
+        })
+        .collect();
+    let length = if family == AF_INET { 4 } else { 16 };
+
+    host.h_name = buffer.alloc_str(name)?;
+    host.h_addrtype = family;
+    host.h_length = length as c_int;
+
+    let alias_list = buffer.alloc::<*mut c_char>(aliases.len() + 1)?;
+    for (i, alias) in aliases.iter().enumerate() {
+        unsafe { *alias_list.add(i) = buffer.alloc_str(alias)? };
+    }
+    unsafe { *alias_list.add(aliases.len()) = ptr::null_mut() };
+    host.h_aliases = alias_list;
+
+    let addr_list = buffer.alloc::<*mut c_char>(octets.len() + 1)?;
+    for (i, octets) in octets.iter().enumerate() {
+        let addr = buffer.alloc::<u8>(length)?;
+        unsafe {
+            ptr::copy_nonoverlapping(octets.as_ptr(), addr, length);
+            *addr_list.add(i) = addr.cast();
+        }
+    }
+    unsafe { *addr_list.add(octets.len()) = ptr::null_mut() };
+    host.h_addr_list = addr_list;
+
+    Some(())
+}
+
+unsafe fn not_found(errnop: *mut c_int, h_errnop: *mut c_int) -> NssStatus {
+    unsafe {
+        *errnop = libc::ENOENT;
+        *h_errnop = HOST_NOT_FOUND;
+    }
+    NSS_STATUS_NOTFOUND
+}
+
+unsafe fn buffer_too_small(errnop: *mut c_int, h_errnop: *mut c_int) -> NssStatus {
+    unsafe {
+        *errnop = libc::ERANGE;
+        *h_errnop = NETDB_INTERNAL;
+    }
+    NSS_STATUS_TRYAGAIN
+}
+
+/// The name being looked up and its addresses, if the store has any.
+unsafe fn lookup(name: *const c_char) -> Option<(String, Vec<IpAddr>)> {
+    let name = unsafe { CStr::from_ptr(name) }.to_str().ok()?.to_string();
+    let addresses = resolve(&read_store()?, &name, SystemTime::now());
+    (!addresses.is_empty()).then_some((name, addresses))
+}
+
+/// Looks up the addresses of both families, as used by `getaddrinfo`.
+///
+/// # Safety
+///
+/// Must be called by glibc, with the arguments its NSS interface specifies.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn _nss_hostie_gethostbyname4_r(
+    name: *const c_char,
+    pat: *mut *mut GaihAddrtuple,
+    buffer: *mut c_char,
+    buflen: usize,
+    errnop: *mut c_int,
+    h_errnop: *mut c_int,
+    ttlp: *mut i32,
+) -> NssStatus {
+    let Some((name, addresses)) = (unsafe { lookup(name) }) else {
+        return unsafe { not_found(errnop, h_errnop) };
+    };
+
+    let mut buffer = Buffer::new(buffer, buflen);
+    let Some(name) = buffer.alloc_str(&name) else {
+        return unsafe { buffer_too_small(errnop, h_errnop) };
+    };
+
+    let mut first: *mut GaihAddrtuple = ptr::null_mut();
+    let mut previous: *mut GaihAddrtuple = ptr::null_mut();
+    for ip in &addresses {
+        let Some(tuple) = buffer.alloc::<GaihAddrtuple>(1) else {
+            return unsafe { buffer_too_small(errnop, h_errnop) };
+        };
+
+        let mut addr = [0u32; 4];
+        let family = match ip {
+            IpAddr::V4(ip) => {
+                addr[0] = u32::from_ne_bytes(ip.octets());
+                AF_INET
+            }
+            IpAddr::V6(ip) => {
+                for (i, chunk) in ip.octets().chunks(4).enumerate() {
+                    addr[i] = u32::from_ne_bytes(chunk.try_into().unwrap());
+                }
+                AF_INET6
+            }
+        };
+
+        unsafe {
+            tuple.write(GaihAddrtuple {
+                next: ptr::null_mut(),
+                name: if first.is_null() {
+                    name
+                } else {
+                    ptr::null_mut()
+                },
+                family,
+                addr,
+                scopeid: 0,
+            });
+            if previous.is_null() {
+                first = tuple;
+            } else {
+                (*previous).next = tuple;
+            }
+        }
+        previous = tuple;
+    }
+
+    unsafe {
+        // Callers may hand in the first tuple to fill, as nss-resolve expects
+        if (*pat).is_null() {
+            *pat = first;
+        } else {
+            **pat = first.read();
+        }
+        if !ttlp.is_null() {
+            *ttlp = 0;
+        }
+    }
+    NSS_STATUS_SUCCESS
+}
+
+/// Looks up the addresses of one family.
+///
+/// # Safety
+///
+/// Must be called by glibc, with the arguments its NSS interface specifies.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn _nss_hostie_gethostbyname3_r(
+    name: *const c_char,
+    af: c_int,
+    host: *mut hostent,
+    buffer: *mut c_char,
+    buflen: usize,
+    errnop: *mut c_int,
+    h_errnop: *mut c_int,
+    ttlp: *mut i32,
+    canonp: *mut *mut c_char,
+) -> NssStatus {
+    if af != AF_INET && af != AF_INET6 {
+        unsafe {
+            *errnop = libc::EAFNOSUPPORT;
+            *h_errnop = NETDB_INTERNAL;
+        }
+        return NSS_STATUS_UNAVAIL;
+    }
+
+    let Some((name, addresses)) = (unsafe { lookup(name) }) else {
+        return unsafe { not_found(errnop, h_errnop) };
+    };
+    let in_family = |ip: &IpAddr| ip.is_ipv4() == (af == AF_INET);
+    if !addresses.iter().any(in_family) {
+        return unsafe { not_found(errnop, h_errnop) };
+    }
+
+    let mut buffer = Buffer::new(buffer, buflen);
+    let host = unsafe { &mut *host };
+    if fill_hostent(&mut buffer, host, &name, &[], af, &addresses).is_none() {
+        return unsafe { buffer_too_small(errnop, h_errnop) };
+    }
+
+    unsafe {
+        if !ttlp.is_null() {
+            *ttlp = 0;
+        }
+        if !canonp.is_null() {
+            *canonp = host.h_name;
+        }
+    }
+    NSS_STATUS_SUCCESS
+}
+
+/// # Safety
+///
+/// Must be called by glibc, with the arguments its NSS interface specifies.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn _nss_hostie_gethostbyname2_r(
+    name: *const c_char,
+    af: c_int,
+    host: *mut hostent,
+    buffer: *mut c_char,
+    buflen: usize,
+    errnop: *mut c_int,
+    h_errnop: *mut c_int,
+) -> NssStatus {
+    unsafe {
+        _nss_hostie_gethostbyname3_r(
+            name,
+            af,
+            host,
+            buffer,
+            buflen,
+            errnop,
+            h_errnop,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    }
+}
+
+/// # Safety
+///
+/// Must be called by glibc, with the arguments its NSS interface specifies.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn _nss_hostie_gethostbyname_r(
+    name: *const c_char,
+    host: *mut hostent,
+    buffer: *mut c_char,
+    buflen: usize,
+    errnop: *mut c_int,
+    h_errnop: *mut c_int,
+) -> NssStatus {
+    unsafe { _nss_hostie_gethostbyname2_r(name, AF_INET, host, buffer, buflen, errnop, h_errnop) }
+}
+
+/// Looks up the hostnames of an address.
+///
+/// # Safety
+///
+/// Must be called by glibc, with the arguments its NSS interface specifies.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn _nss_hostie_gethostbyaddr2_r(
+    addr: *const c_void,
+    len: socklen_t,
+    af: c_int,
+    host: *mut hostent,
+    buffer: *mut c_char,
+    buflen: usize,
+    errnop: *mut c_int,
+    h_errnop: *mut c_int,
+    ttlp: *mut i32,
+) -> NssStatus {
+    let bytes = unsafe { std::slice::from_raw_parts(addr.cast::<u8>(), len as usize) };
+    let ip = match (af, bytes.len()) {
+        (AF_INET, 4) => IpAddr::from(<[u8; 4]>::try_from(bytes).unwrap()),
+        (AF_INET6, 16) => IpAddr::from(<[u8; 16]>::try_from(bytes).unwrap()),
+        _ => {
+            unsafe {
+                *errnop = libc::EINVAL;
+                *h_errnop = NETDB_INTERNAL;
+            }
+            return NSS_STATUS_UNAVAIL;
+        }
+    };
+
+    let names = read_store()
+        .map(|hosts| reverse(&hosts, ip, SystemTime::now()))
+        .unwrap_or_default();
+    let Some((name, aliases)) = names.split_first() else {
+        return unsafe { not_found(errnop, h_errnop) };
+    };
+
+    let mut buffer = Buffer::new(buffer, buflen);
+    let host = unsafe { &mut *host };
+    if fill_hostent(&mut buffer, host, name, aliases, af, &[ip]).is_none() {
+        return unsafe { buffer_too_small(errnop, h_errnop) };
+    }
+
+    if !ttlp.is_null() {
+        unsafe { *ttlp = 0 };
+    }
+    NSS_STATUS_SUCCESS
+}
+
+/// # Safety
+///
+/// Must be called by glibc, with the arguments its NSS interface specifies.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn _nss_hostie_gethostbyaddr_r(
+    addr: *const c_void,
+    len: socklen_t,
+    af: c_int,
+    host: *mut hostent,
+    buffer: *mut c_char,
+    buflen: usize,
+    errnop: *mut c_int,
+    h_errnop: *mut c_int,
+) -> NssStatus {
+    unsafe {
+        _nss_hostie_gethostbyaddr2_r(
+            addr,
+            len,
+            af,
+            host,
+            buffer,
+            buflen,
+            errnop,
+            h_errnop,
+            ptr::null_mut(),
+        )
+    }
+}
@@ -0,0 +1,149 @@
+#![cfg(target_os = "linux")]
+
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::fs;
+use std::net::IpAddr;
+use std::ptr;
+use std::time::{Duration, SystemTime};
+
+use hostie::hosts::HostsFile;
+use nss_hostie::{_nss_hostie_gethostbyaddr_r, _nss_hostie_gethostbyname4_r, GaihAddrtuple};
+use nss_hostie::{resolve, reverse};
+
+const STORE: &str = "\
+127.0.0.1 myapp.test www.myapp.test
+::1 myapp.test
+10.0.0.1 *.preview.test
+10.0.0.2 *.pr-1.preview.test
+10.0.0.3 old.test # hostie:expires=2001-01-01T00:00:00Z
+";
+
+fn ips(addresses: &[&str]) -> Vec<IpAddr> {
+    addresses.iter().map(|ip| ip.parse().unwrap()).collect()
+}
+
+#[test]
+fn test_resolve_exact_wildcard_and_expired() {
+    let hosts = HostsFile::parse(STORE);
+    let now = SystemTime::now();
+
+    assert_eq!(
+        resolve(&hosts, "MyApp.test.", now),
+        ips(&["127.0.0.1", "::1"])
+    );
+    assert_eq!(resolve(&hosts, "a.preview.test", now), ips(&["10.0.0.1"]));
+    // The most specific wildcard wins
+    assert_eq!(
+        resolve(&hosts, "api.pr-1.preview.test", now),
+        ips(&["10.0.0.2"])
+    );
+    // A wildcard doesn't cover the domain itself
+    assert!(resolve(&hosts, "preview.test", now).is_empty());
+    assert!(resolve(&hosts, "old.test", now).is_empty());
+    assert!(
+        !resolve(
+            &hosts,
+            "old.test",
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1)
+        )
+        .is_empty()
+    );
+
+    assert_eq!(
+        reverse(&hosts, "127.0.0.1".parse().unwrap(), now),
+        ["myapp.test", "www.myapp.test"]
+    );
+    assert!(reverse(&hosts, "10.0.0.1".parse().unwrap(), now).is_empty());
+}
+
+/// The only test touching the environment, as the NSS entry points read the
+/// store of the user in `HOME`.
+#[test]
+fn test_nss_entry_points_read_the_store() {
+    let home = tempfile::tempdir().unwrap();
+    let store = home.path().join(".local/share/hostie/hosts");
+    fs::create_dir_all(store.parent().unwrap()).unwrap();
+    fs::write(&store, STORE).unwrap();
+    unsafe {
+        std::env::remove_var("XDG_DATA_HOME");
+        std::env::set_var("HOME", home.path());
+    }
+
+    let mut buffer = vec![0 as c_char; 1024];
+    let mut errno: c_int = 0;
+    let mut h_errno: c_int = 0;
+
+    let name = CString::new("myapp.test").unwrap();
+    let mut tuple: *mut GaihAddrtuple = ptr::null_mut();
+    let status = unsafe {
+        _nss_hostie_gethostbyname4_r(
+            name.as_ptr(),
+            &mut tuple,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut errno,
+            &mut h_errno,
+            ptr::null_mut(),
+        )
+    };
+    assert_eq!(status, 1);
+    let first = unsafe { &*tuple };
+    assert_eq!(unsafe { CStr::from_ptr(first.name) }, name.as_c_str());
+    assert_eq!(first.family, libc::AF_INET);
+    assert_eq!(first.addr[0].to_ne_bytes(), [127, 0, 0, 1]);
+    let second = unsafe { &*first.next };
+    assert_eq!(second.family, libc::AF_INET6);
+    assert!(second.next.is_null());
+
+    // A buffer that's too small asks the caller to retry with a larger one
+    let mut tuple: *mut GaihAddrtuple = ptr::null_mut();
+    let status = unsafe {
+        _nss_hostie_gethostbyname4_r(
+            name.as_ptr(),
+            &mut tuple,
+            buffer.as_mut_ptr(),
+            8,
+            &mut errno,
+            &mut h_errno,
+            ptr::null_mut(),
+        )
+    };
+    assert_eq!((status, errno), (-2, libc::ERANGE));
+
+    let unknown = CString::new("unknown.test").unwrap();
+    let status = unsafe {
+        _nss_hostie_gethostbyname4_r(
+            unknown.as_ptr(),
+            &mut tuple,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut errno,
+            &mut h_errno,
+            ptr::null_mut(),
+        )
+    };
+    assert_eq!(status, 0);
+
+    let addr = [127u8, 0, 0, 1];
+    let mut host: libc::hostent = unsafe { std::mem::zeroed() };
+    let status = unsafe {
+        _nss_hostie_gethostbyaddr_r(
+            addr.as_ptr().cast(),
+            4,
+            libc::AF_INET,
+            &mut host,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut errno,
+            &mut h_errno,
+        )
+    };
+    assert_eq!(status, 1);
+    assert_eq!(
+        unsafe { CStr::from_ptr(host.h_name) }.to_str().unwrap(),
+        "myapp.test"
+    );
+    let alias = unsafe { CStr::from_ptr(*host.h_aliases) };
+    assert_eq!(alias.to_str().unwrap(), "www.myapp.test");
+    assert!(unsafe { *host.h_aliases.add(1) }.is_null());
+}
@@ -0,0 +1,131 @@
+//! Per-user hostname aliases through the `HOSTALIASES` file.
+//!
+//! glibc resolves a name without dots through the file named by the
+//! `HOSTALIASES` environment variable before anything else, e.g. a line
+//! `db db.staging.internal` makes `db` resolve like `db.staging.internal`.
+//! Unlike the hosts file, it belongs to the user, so no root is needed.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use colored::{ColoredString, Colorize};
+
+use crate::Error;
+use crate::hosts::is_hostname;
+
+/// The aliases file: `$HOSTALIASES` if set, otherwise `~/.hosts`.
+pub fn aliases_path() -> PathBuf {
+    if let Some(path) = env::var_os("HOSTALIASES").filter(|path| !path.is_empty()) {
+        return PathBuf::from(path);
+    }
+
+    let home = env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    home.join(".hosts")
+}
+
+pub fn add_alias(alias: &str, hostname: &str) -> Result<ColoredString, Error> {
+    // glibc only consults the file for names without dots
+    if alias.contains('.') || !is_hostname(alias) {
+        return Err(Error::Generic(
+            format!(
+                "Aliases must be single names without dots: {}",
+                alias.magenta().bold()
+            )
+            .red(),
+        ));
+    }
+    if !is_hostname(hostname) {
+        return Err(Error::Generic(
+            format!("Not a valid hostname: {}", hostname.magenta().bold()).red(),
+        ));
+    }
+
+    let mut lines = read_lines()?;
+    if let Some(existing) = lines
+        .iter()
+        .find_map(|line| parse(line).filter(|(a, _)| *a == alias))
+    {
+        return Err(Error::Generic(
+            format!(
+                "Alias already exists: {} -> {}",
+                alias.magenta().bold(),
+                existing.1.magenta().bold()
+            )
+            .red(),
+        ));
+    }
+
+    lines.push(format!("{alias} {hostname}"));
+    write_lines(&lines)?;
+
+    Ok(format!(
+        "Added alias to {}: {} -> {}",
+        aliases_path().display(),
+        alias.magenta().bold(),
+        hostname.magenta().bold()
+    )
+    .green())
+}
+
+pub fn remove_alias(alias: &str) -> Result<ColoredString, Error> {
+    let mut lines = read_lines()?;
+    let before = lines.len();
+    lines.retain(|line| parse(line).is_none_or(|(a, _)| a != alias));
+    if lines.len() == before {
+        return Err(Error::Generic(
+            format!("Alias does not exist: {}", alias.magenta().bold()).red(),
+        ));
+    }
+    write_lines(&lines)?;
+
+    Ok(format!("Removed alias: {}", alias.magenta().bold()).green())
+}
+
+pub fn list_aliases() -> Result<ColoredString, Error> {
+    let aliases = read_lines()?
+        .iter()
+        .filter_map(|line| parse(line))
+        .map(|(alias, hostname)| {
+            format!(
+                "{} -> {}",
+                alias.magenta().bold(),
+                hostname.magenta().bold()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok(aliases.green())
+}
+
+/// The line to `eval` in a shell profile so programs pick up the aliases.
+pub fn print_env() -> Result<ColoredString, Error> {
+    let path = aliases_path().to_string_lossy().replace('\'', r"'\''");
+    Ok(format!("export HOSTALIASES='{path}'").normal())
+}
+
+/// `(alias, hostname)` for an alias line, `None` for comments and the like.
+fn parse(line: &str) -> Option<(&str, &str)> {
+    let line = line.split('#').next().unwrap_or_default();
+    let mut fields = line.split_whitespace();
+    Some((fields.next()?, fields.next()?))
+}
+
+/// The lines of the aliases file, which doesn't have to exist yet.
+fn read_lines() -> io::Result<Vec<String>> {
+    match fs::read_to_string(aliases_path()) {
+        Ok(contents) => Ok(contents.lines().map(String::from).collect()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
+}
+
+fn write_lines(lines: &[String]) -> io::Result<()> {
+    let mut contents = lines.join("\n");
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+    fs::write(aliases_path(), contents)
+}
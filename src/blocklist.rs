@@ -0,0 +1,376 @@
+//! Blocklist subscriptions.
+//!
+//! Each list is imported from a StevenBlack-style file (`0.0.0.0 domain` per
+//! line) into its own managed block, together with the source it came from so
+//! that `blocklist update` can refresh it. Domains on the allowlist, and
+//! domains that already have a hand-written entry, are never imported.
+//! Neither are domains another blocklist already blocks, so removing that
+//! list unblocks them until the others are updated.
+
+use std::collections::HashSet;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use clap::Parser;
+use colored::{ColoredString, Colorize};
+
+use crate::hosts::{Entry, HostsFile, Line};
+use crate::{Error, get_hosts_path, update_hosts};
+
+const BLOCK_PREFIX: &str = "blocklist:";
+const ALLOWLIST_BLOCK: &str = "allowlist";
+const SOURCE_PREFIX: &str = "# source: ";
+const BLOCKED_IP: &str = "0.0.0.0";
+
+/// Hostnames found in published blocklists that must never be blocked.
+const IGNORED_HOSTNAMES: &[&str] = &[
+    "0.0.0.0",
+    "local",
+    "localhost",
+    "localhost.localdomain",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+];
+
+#[derive(Parser)]
+pub enum BlocklistCommand {
+    /// Import a blocklist from a file or URL
+    Add {
+        /// A name to refer to the blocklist by
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// Path or http(s) URL of a hosts-format blocklist
+        #[arg(value_name = "SOURCE")]
+        source: String,
+    },
+    /// Re-import blocklists from their sources
+    Update {
+        /// Only update this blocklist
+        #[arg(value_name = "NAME")]
+        name: Option<String>,
+    },
+    /// Remove a blocklist and all of its entries
+    Remove {
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+    /// List imported blocklists and allowed domains
+    List,
+    /// Never block a domain, whatever the blocklists say
+    Allow {
+        #[arg(value_name = "DOMAIN")]
+        domain: String,
+    },
+    /// Take a domain off the allowlist again
+    Disallow {
+        #[arg(value_name = "DOMAIN")]
+        domain: String,
+    },
+}
+
+pub fn run(cmd: BlocklistCommand) -> Result<ColoredString, Error> {
+    match cmd {
+        BlocklistCommand::Add { name, source } => add_blocklist(&name, &source),
+        BlocklistCommand::Update { name } => update_blocklists(name.as_deref()),
+        BlocklistCommand::Remove { name } => remove_blocklist(&name),
+        BlocklistCommand::List => list_blocklists(),
+        BlocklistCommand::Allow { domain } => allow_domain(&domain),
+        BlocklistCommand::Disallow { domain } => disallow_domain(&domain),
+    }
+}
+
+/// Whether an entry in the given managed block comes from a blocklist.
+pub fn is_blocklist_block(block: Option<&str>) -> bool {
+    block.is_some_and(|name| name.starts_with(BLOCK_PREFIX))
+}
+
+/// Removes `domain` from all blocklists, returning whether it was on any.
+/// Like a hand-written entry, it won't be imported again on `update`.
+pub fn unblock(hosts: &mut HostsFile, domain: &str) -> bool {
+    let mut found = false;
+    for block in blocklist_blocks(hosts) {
+        let lines = hosts.block(&block).unwrap_or_default();
+        let kept: Vec<Line> = lines
+            .iter()
+            .filter(|line| !matches!(line, Line::Entry(entry) if entry.has_hostname(domain)))
+            .cloned()
+            .collect();
+        found |= kept.len() < lines.len();
+        hosts.set_block(&block, kept);
+    }
+    found
+}
+
+fn add_blocklist(name: &str, source: &str) -> Result<ColoredString, Error> {
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(Error::Generic(
+            format!("Invalid blocklist name: {name:?}").red(),
+        ));
+    }
+
+    let exists = |hosts: &HostsFile| {
+        if hosts.block(&block_name(name)).is_some() {
+            return Err(Error::Generic(
+                format!("Blocklist already exists: {}", name.yellow().bold()).red(),
+            ));
+        }
+        Ok(())
+    };
+    exists(&HostsFile::read(get_hosts_path())?)?;
+
+    // Store local paths absolutely so `update` works from any directory
+    let source = if is_url(source) {
+        source.to_string()
+    } else {
+        fs::canonicalize(source)?.display().to_string()
+    };
+
+    // Downloaded before locking the hosts file, which may take a while
+    let contents = fetch(&source)?;
+    let count = update_hosts(|hosts| {
+        exists(hosts)?;
+        Ok(import(hosts, name, &source, &contents))
+    })?;
+
+    Ok(format!(
+        "Imported blocklist {}: {count} entries",
+        name.yellow().bold()
+    )
+    .green())
+}
+
+fn update_blocklists(only: Option<&str>) -> Result<ColoredString, Error> {
+    let hosts = HostsFile::read(get_hosts_path())?;
+
+    let names: Vec<String> = hosts
+        .block_names()
+        .into_iter()
+        .filter_map(|block| block.strip_prefix(BLOCK_PREFIX))
+        .filter(|name| only.is_none_or(|only| only == *name))
+        .map(String::from)
+        .collect();
+
+    if names.is_empty() {
+        return Err(Error::Generic(match only {
+            Some(name) => format!("Blocklist does not exist: {}", name.yellow().bold()).red(),
+            None => "No blocklists to update".red(),
+        }));
+    }
+
+    // Downloaded before locking the hosts file, which may take a while
+    let mut fetched = Vec::new();
+    for name in names {
+        let source = source_of(&hosts, &name)
+            .ok_or_else(|| Error::Generic(format!("Blocklist {name} has no source line").red()))?;
+        let contents = fetch(&source)?;
+        fetched.push((name, source, contents));
+    }
+
+    let updated = update_hosts(|hosts| {
+        let mut updated = Vec::new();
+        for (name, source, contents) in &fetched {
+            // Unless it was removed in the meantime
+            if hosts.block(&block_name(name)).is_none() {
+                continue;
+            }
+            let count = import(hosts, name, source, contents);
+            updated.push(format!(
+                "Updated blocklist {}: {count} entries",
+                name.yellow().bold()
+            ));
+        }
+        Ok(updated)
+    })?;
+
+    Ok(updated.join("\n").green())
+}
+
+fn remove_blocklist(name: &str) -> Result<ColoredString, Error> {
+    update_hosts(|hosts| {
+        if hosts.remove_block(&block_name(name)).is_none() {
+            return Err(Error::Generic(
+                format!("Blocklist does not exist: {}", name.yellow().bold()).red(),
+            ));
+        }
+        Ok(())
+    })?;
+
+    Ok(format!("Removed blocklist {}", name.yellow().bold()).green())
+}
+
+fn list_blocklists() -> Result<ColoredString, Error> {
+    let hosts = HostsFile::read(get_hosts_path())?;
+
+    let mut lines: Vec<String> = hosts
+        .block_names()
+        .into_iter()
+        .filter_map(|block| block.strip_prefix(BLOCK_PREFIX))
+        .map(|name| {
+            let count = hosts
+                .block(&block_name(name))
+                .unwrap_or_default()
+                .iter()
+                .filter(|line| matches!(line, Line::Entry(_)))
+                .count();
+            let source = source_of(&hosts, name).unwrap_or_default();
+            format!("{} {count} entries ({source})", name.yellow().bold())
+        })
+        .collect();
+
+    lines.extend(
+        allowlist(&hosts)
+            .iter()
+            .map(|domain| format!("allowed {}", domain.magenta().bold())),
+    );
+
+    Ok(lines.join("\n").green())
+}
+
+fn allow_domain(domain: &str) -> Result<ColoredString, Error> {
+    update_hosts(|hosts| {
+        let mut allowed = allowlist(hosts);
+        if allowed.iter().any(|d| d == domain) {
+            return Err(Error::Generic(
+                format!("Domain is already allowed: {}", domain.magenta().bold()).red(),
+            ));
+        }
+        allowed.push(domain.to_string());
+        set_allowlist(hosts, &allowed);
+
+        // Unblock it right away instead of waiting for the next update
+        unblock(hosts, domain);
+        Ok(())
+    })?;
+
+    Ok(format!("Allowed domain: {}", domain.magenta().bold()).green())
+}
+
+fn disallow_domain(domain: &str) -> Result<ColoredString, Error> {
+    update_hosts(|hosts| {
+        let mut allowed = allowlist(hosts);
+        let before = allowed.len();
+        allowed.retain(|d| d != domain);
+        if allowed.len() == before {
+            return Err(Error::Generic(
+                format!("Domain is not allowed: {}", domain.magenta().bold()).red(),
+            ));
+        }
+        set_allowlist(hosts, &allowed);
+        Ok(())
+    })?;
+
+    Ok(format!(
+        "Removed {} from the allowlist; it will be blocked again after the next update",
+        domain.magenta().bold()
+    )
+    .green())
+}
+
+/// Downloads or reads the blocklist at `source`.
+fn fetch(source: &str) -> Result<String, Error> {
+    if is_url(source) {
+        Ok(ureq::get(source).call()?.body_mut().read_to_string()?)
+    } else {
+        Ok(fs::read_to_string(Path::new(source))?)
+    }
+}
+
+/// Replaces the contents of the blocklist's block with the domains in
+/// `contents`, fetched from `source`, returning the number of imported
+/// entries.
+fn import(hosts: &mut HostsFile, name: &str, source: &str, contents: &str) -> usize {
+    let block = block_name(name);
+    let mut skip: HashSet<String> = allowlist(hosts).into_iter().collect();
+    skip.extend(
+        hosts
+            .entries_by_block()
+            .filter(|(b, _)| *b != Some(block.as_str()))
+            .flat_map(|(_, entry)| entry.hostnames.iter().cloned()),
+    );
+
+    let mut lines = vec![Line::Other(format!("{SOURCE_PREFIX}{source}"))];
+    for domain in parse_domains(contents) {
+        if skip.insert(domain.to_string()) {
+            lines.push(Line::Entry(Entry::new(BLOCKED_IP, domain)));
+        }
+    }
+
+    let count = lines.len() - 1;
+    hosts.set_block(&block, lines);
+
+    count
+}
+
+/// Extracts the blocked domains from a hosts-format list. Lines holding just
+/// a domain, as some lists use, are accepted too.
+fn parse_domains(contents: &str) -> impl Iterator<Item = &str> {
+    contents.lines().flat_map(|line| {
+        let data = line.split('#').next().unwrap_or_default();
+        let fields: Vec<&str> = data.split_whitespace().collect();
+        let domains = match fields.as_slice() {
+            [domain] if domain.parse::<IpAddr>().is_err() => &fields[..],
+            [_] => &[],
+            [_ip, domains @ ..] => domains,
+            [] => &[],
+        };
+        domains
+            .iter()
+            .copied()
+            .filter(|domain| !IGNORED_HOSTNAMES.contains(domain))
+            .collect::<Vec<_>>()
+    })
+}
+
+fn source_of(hosts: &HostsFile, name: &str) -> Option<String> {
+    hosts
+        .block(&block_name(name))?
+        .iter()
+        .find_map(|line| match line {
+            Line::Other(text) => text.strip_prefix(SOURCE_PREFIX).map(String::from),
+            Line::Entry(_) => None,
+        })
+}
+
+fn blocklist_blocks(hosts: &HostsFile) -> Vec<String> {
+    hosts
+        .block_names()
+        .into_iter()
+        .filter(|block| block.starts_with(BLOCK_PREFIX))
+        .map(String::from)
+        .collect()
+}
+
+fn allowlist(hosts: &HostsFile) -> Vec<String> {
+    hosts
+        .block(ALLOWLIST_BLOCK)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|line| match line {
+            Line::Other(text) => text.strip_prefix("# ").map(String::from),
+            Line::Entry(_) => None,
+        })
+        .collect()
+}
+
+fn set_allowlist(hosts: &mut HostsFile, allowed: &[String]) {
+    if allowed.is_empty() {
+        hosts.remove_block(ALLOWLIST_BLOCK);
+    } else {
+        let lines = allowed
+            .iter()
+            .map(|domain| Line::Other(format!("# {domain}")))
+            .collect();
+        hosts.set_block(ALLOWLIST_BLOCK, lines);
+    }
+}
+
+fn block_name(name: &str) -> String {
+    format!("{BLOCK_PREFIX}{name}")
+}
+
+fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
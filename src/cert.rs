@@ -0,0 +1,236 @@
+//! TLS certificates for the hostnames in the hosts file.
+//!
+//! A local certificate authority is created the first time a certificate is
+//! issued. Once it is trusted, every certificate it signs is too, so new
+//! `.test` names work in the browser without warnings.
+//!
+//! The CA belongs to the user, whose browser trusts it, so under `sudo` it is
+//! kept in the invoking user's data directory rather than root's.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::Parser;
+use colored::{ColoredString, Colorize};
+use rcgen::{
+    BasicConstraints, CertificateParams, CertifiedIssuer, DnType, ExtendedKeyUsagePurpose, IsCa,
+    Issuer, KeyPair, KeyUsagePurpose,
+};
+use time::OffsetDateTime;
+
+use crate::hosts::{HostsFile, is_hostname};
+use crate::paths::{sudo_user, sudo_user_data_dir};
+use crate::{Error, get_hosts_path};
+
+const CA_NAME: &str = "hostie development CA";
+const CA_VALIDITY: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+/// The longest validity Apple platforms accept for server certificates.
+const CERT_VALIDITY: Duration = Duration::from_secs(825 * 24 * 60 * 60);
+
+#[derive(Parser)]
+pub struct Cert {
+    /// The hostname to issue a certificate for, e.g. `myapp.test` or
+    /// `*.myapp.test`
+    #[arg(value_name = "HOSTNAME")]
+    hostname: String,
+}
+
+pub fn issue_certificate(cert: &Cert) -> Result<ColoredString, Error> {
+    let issued = issue(&cert.hostname)?;
+    Ok(issued.describe().green())
+}
+
+/// Paths of a newly issued certificate.
+pub struct Issued {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub names: Vec<String>,
+    /// Set when the CA had to be created for this certificate
+    pub new_ca: Option<PathBuf>,
+}
+
+impl Issued {
+    pub fn describe(&self) -> String {
+        let mut lines = Vec::new();
+        if let Some(ca) = &self.new_ca {
+            lines.push(format!(
+                "Created a local CA at {}, add it to your trust store to trust the certificates it issues",
+                ca.display()
+            ));
+        }
+        lines.push(format!(
+            "Issued a certificate for {}",
+            self.names.join(", ").magenta().bold()
+        ));
+        lines.push(format!("Certificate: {}", self.cert.display()));
+        lines.push(format!("Key: {}", self.key.display()));
+        lines.join("\n")
+    }
+}
+
+/// Issues a certificate for `hostname` and the aliases it has in the hosts
+/// file, creating the CA first if needed.
+pub fn issue(hostname: &str) -> Result<Issued, Error> {
+    let bare = hostname.strip_prefix("*.").unwrap_or(hostname);
+    if !is_hostname(bare) {
+        return Err(Error::Generic(
+            format!("Not a valid hostname: {}", hostname.magenta().bold()).red(),
+        ));
+    }
+
+    // Looked up while still root, which is what tells that sudo was used
+    let dir = sudo_user_data_dir();
+    let _user = AsSudoUser::start()?;
+    let new_ca = create_ca_if_missing(&dir)?;
+    let issuer = load_ca(&dir)?;
+
+    // Aliases are the other hostnames on the lines that mention `hostname`
+    let mut names = vec![hostname.to_string()];
+    if let Ok(hosts) = HostsFile::read(get_hosts_path()) {
+        for entry in hosts.entries().filter(|entry| entry.has_hostname(hostname)) {
+            for alias in &entry.hostnames {
+                if !names.contains(alias) {
+                    names.push(alias.clone());
+                }
+            }
+        }
+    }
+
+    let mut params = CertificateParams::new(names.clone())?;
+    params.distinguished_name.push(DnType::CommonName, hostname);
+    params.not_before = OffsetDateTime::now_utc() - Duration::from_secs(24 * 60 * 60);
+    params.not_after = OffsetDateTime::now_utc() + CERT_VALIDITY;
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyEncipherment,
+    ];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.use_authority_key_identifier_extension = true;
+
+    let key = KeyPair::generate()?;
+    let cert = params.signed_by(&key, &issuer)?;
+
+    let (cert_path, key_path) = files_in(&dir, hostname);
+    if let Some(certs) = cert_path.parent() {
+        fs::create_dir_all(certs)?;
+    }
+    fs::write(&cert_path, cert.pem())?;
+    write_private(&key_path, &key.serialize_pem())?;
+
+    Ok(Issued {
+        cert: cert_path,
+        key: key_path,
+        names,
+        new_ca,
+    })
+}
+
+/// Where the certificate and key for `hostname` are kept, whether or not they
+/// have been issued yet.
+pub fn files(hostname: &str) -> (PathBuf, PathBuf) {
+    files_in(&sudo_user_data_dir(), hostname)
+}
+
+fn files_in(dir: &Path, hostname: &str) -> (PathBuf, PathBuf) {
+    // Named like mkcert does, so `*.myapp.test` gets `_wildcard.myapp.test.pem`
+    let file_name = hostname.replace('*', "_wildcard");
+    let certs = dir.join("certs");
+    (
+        certs.join(format!("{file_name}.pem")),
+        certs.join(format!("{file_name}-key.pem")),
+    )
+}
+
+/// Creates the CA in `dir` unless it exists, returning its path if created.
+fn create_ca_if_missing(dir: &Path) -> Result<Option<PathBuf>, Error> {
+    let cert_path = dir.join("ca.pem");
+    if cert_path.exists() && dir.join("ca-key.pem").exists() {
+        return Ok(None);
+    }
+
+    let mut params = CertificateParams::default();
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, CA_NAME);
+    params
+        .distinguished_name
+        .push(DnType::OrganizationName, "hostie");
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    params.not_before = OffsetDateTime::now_utc() - Duration::from_secs(24 * 60 * 60);
+    params.not_after = OffsetDateTime::now_utc() + CA_VALIDITY;
+
+    let key = KeyPair::generate()?;
+    let key_pem = key.serialize_pem();
+    let ca = CertifiedIssuer::self_signed(params, key)?;
+
+    fs::create_dir_all(dir)?;
+    write_private(&dir.join("ca-key.pem"), &key_pem)?;
+    fs::write(&cert_path, ca.pem())?;
+
+    Ok(Some(cert_path))
+}
+
+fn load_ca(dir: &Path) -> Result<Issuer<'static, KeyPair>, Error> {
+    let key = KeyPair::from_pem(&fs::read_to_string(dir.join("ca-key.pem"))?)?;
+    Ok(Issuer::from_ca_cert_pem(
+        &fs::read_to_string(dir.join("ca.pem"))?,
+        key,
+    )?)
+}
+
+/// Acts as the user behind `sudo`, if any, until dropped. Files are then
+/// created as theirs, as if they had run hostie themselves, and root never
+/// writes into a directory they control.
+struct AsSudoUser {
+    switched: bool,
+}
+
+impl AsSudoUser {
+    fn start() -> io::Result<Self> {
+        #[cfg(unix)]
+        if let Some(user) = sudo_user() {
+            // The group first, as that needs root
+            if unsafe { libc::setegid(user.gid) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if unsafe { libc::seteuid(user.uid) } != 0 {
+                let err = io::Error::last_os_error();
+                unsafe { libc::setegid(0) };
+                return Err(err);
+            }
+            return Ok(AsSudoUser { switched: true });
+        }
+        Ok(AsSudoUser { switched: false })
+    }
+}
+
+impl Drop for AsSudoUser {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.switched {
+            unsafe {
+                libc::seteuid(0);
+                libc::setegid(0);
+            }
+        }
+    }
+}
+
+/// Writes a private key so that only its owner can read it.
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    io::Write::write_all(&mut options.open(path)?, contents.as_bytes())
+}
@@ -0,0 +1,185 @@
+//! Shell completions.
+//!
+//! The script `hostie completions <shell>` prints doesn't list hostie's
+//! arguments itself but asks hostie for them, by running it with
+//! `COMPLETE=<shell>` set, on every TAB. That way `remove` can offer the
+//! entries that are in the hosts file right now.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use clap::Parser;
+use clap_complete::env::Shells;
+use clap_complete::{CompletionCandidate, Shell};
+use colored::{ColoredString, Colorize};
+
+use crate::hosts::HostsFile;
+use crate::{Error, blocklist, config, get_hosts_path};
+
+/// The environment variable the completion scripts set when calling hostie.
+pub const COMPLETE_VAR: &str = "COMPLETE";
+
+#[derive(Parser)]
+pub struct Completions {
+    /// The shell to print the completion script for
+    #[arg(value_name = "SHELL")]
+    shell: Shell,
+}
+
+pub fn print_completions(completions: &Completions) -> Result<ColoredString, Error> {
+    let name = completions.shell.to_string();
+    let shells = Shells::builtins();
+    let shell = shells
+        .completer(&name)
+        .expect("every clap_complete shell has a completer");
+
+    // The script calls back into this very binary, wherever it is installed
+    let completer = std::env::current_exe()?;
+    let mut script = Vec::new();
+    shell.write_registration(
+        COMPLETE_VAR,
+        "hostie",
+        "hostie",
+        &completer.to_string_lossy(),
+        &mut script,
+    )?;
+
+    Ok(String::from_utf8_lossy(&script).trim_end().normal())
+}
+
+/// The options that choose the hosts file, as given on the command line
+/// being completed.
+#[derive(Default)]
+pub struct CommandLine {
+    pub store: bool,
+    pub hosts_file: Option<String>,
+    pub root: Option<PathBuf>,
+    pub netns: Option<String>,
+}
+
+/// The IP address given to `remove` on the command line being completed.
+static REMOVE_IP: OnceLock<String> = OnceLock::new();
+
+/// When called from a completion script, reads the words before the one
+/// being completed, which clap doesn't parse until the candidates are
+/// needed, and remembers the IP address given to `remove` for
+/// `hostname_candidates`.
+pub fn read_command_line() -> Option<CommandLine> {
+    if env::var_os(COMPLETE_VAR).is_none_or(|shell| shell.is_empty() || shell == "0") {
+        return None;
+    }
+
+    // The script passes `-- hostie <words>`, and bash, zsh and elvish the
+    // index of the word being completed, which the others put last
+    let args: Vec<String> = env::args().collect();
+    let words = &args[args.iter().position(|arg| arg == "--")? + 1..];
+    let current = env::var("_CLAP_COMPLETE_INDEX")
+        .ok()
+        .and_then(|index| index.parse().ok())
+        .unwrap_or(words.len().saturating_sub(1))
+        .min(words.len());
+    let words = words.get(1..current).unwrap_or_default();
+
+    let mut line = CommandLine::default();
+    let mut positionals = Vec::new();
+    let mut i = 0;
+    while i < words.len() {
+        let word = &words[i];
+        i += 1;
+        let (option, inline_value) = match word.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => (option, Some(value)),
+            _ => (word.as_str(), None),
+        };
+        let mut value = || match inline_value {
+            Some(value) => Some(value.to_string()),
+            None => {
+                i += 1;
+                words.get(i - 1).cloned()
+            }
+        };
+
+        match option {
+            // The command `run` and `sandbox` start
+            "--" => break,
+            "--store" => line.store = true,
+            // Several files all get the same change, offer the first one's
+            "--hosts-file" => {
+                let path = value();
+                line.hosts_file = line.hosts_file.or(path);
+            }
+            "--root" => line.root = value().map(PathBuf::from),
+            "--netns" => line.netns = value(),
+            "--tag" => {
+                value();
+            }
+            _ if option.starts_with('-') => {}
+            _ => positionals.push(word.as_str()),
+        }
+    }
+
+    if let ["remove", ip, ..] = positionals.as_slice() {
+        let _ = REMOVE_IP.set(ip.to_string());
+    }
+    Some(line)
+}
+
+/// The IP addresses `remove` accepts, with the hostnames they go with.
+pub fn ip_candidates() -> Vec<CompletionCandidate> {
+    let mut hostnames: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (ip, hostname) in removable_entries() {
+        hostnames.entry(ip).or_default().push(hostname);
+    }
+
+    hostnames
+        .into_iter()
+        .map(|(ip, hostnames)| CompletionCandidate::new(ip).help(Some(hostnames.join(" ").into())))
+        .collect()
+}
+
+/// The hostnames `remove` accepts, with the IP addresses they go with. Only
+/// those of the IP address already given are offered.
+pub fn hostname_candidates() -> Vec<CompletionCandidate> {
+    let mut ips: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (ip, hostname) in removable_entries() {
+        if REMOVE_IP.get().is_none_or(|given| *given == ip) {
+            ips.entry(hostname).or_default().push(ip);
+        }
+    }
+
+    ips.into_iter()
+        .map(|(hostname, ips)| CompletionCandidate::new(hostname).help(Some(ips.join(" ").into())))
+        .collect()
+}
+
+/// The tags of entries in the hosts file.
+pub fn tag_candidates() -> Vec<CompletionCandidate> {
+    let Ok(hosts) = HostsFile::read(get_hosts_path()) else {
+        return Vec::new();
+    };
+
+    let mut tags: Vec<&String> = hosts.entries().flat_map(|entry| &entry.tags).collect();
+    tags.sort();
+    tags.dedup();
+    tags.into_iter().map(CompletionCandidate::new).collect()
+}
+
+/// `(ip, hostname)` of every entry `remove` would remove: those with a single
+/// hostname that isn't protected, outside of blocklists.
+fn removable_entries() -> Vec<(String, String)> {
+    let Ok(hosts) = HostsFile::read(get_hosts_path()) else {
+        return Vec::new();
+    };
+
+    hosts
+        .entries_by_block()
+        .filter(|(block, _)| !blocklist::is_blocklist_block(*block))
+        .filter_map(|(_, entry)| match entry.hostnames.as_slice() {
+            [hostname] if !config().protected.contains(hostname) => {
+                Some((entry.ip.clone(), hostname.clone()))
+            }
+            _ => None,
+        })
+        .collect()
+}
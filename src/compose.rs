@@ -0,0 +1,276 @@
+//! Entries for the services of a docker-compose project.
+//!
+//! Hostnames are taken from service names, `hostname`, `extra_hosts`, Traefik
+//! `Host(...)` router rules and `VIRTUAL_HOST` (as used by nginx-proxy), and
+//! written to a managed block named after the project.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use colored::{ColoredString, Colorize};
+use serde_yaml::Value;
+
+use crate::hosts::{Entry, is_hostname};
+use crate::{Error, update_hosts};
+
+/// Files `docker compose` looks for when no `-f` is given, in order.
+const DEFAULT_FILES: &[&str] = &[
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yaml",
+    "docker-compose.yml",
+];
+
+#[derive(Parser)]
+pub enum ComposeCommand {
+    /// Add entries for the hostnames in a compose file
+    Up {
+        #[command(flatten)]
+        project: Project,
+
+        /// The IP address to point the hostnames at
+        #[arg(long, default_value = "127.0.0.1")]
+        ip: String,
+    },
+    /// Remove the entries added by `compose up`
+    Down {
+        #[command(flatten)]
+        project: Project,
+    },
+}
+
+#[derive(Parser)]
+pub struct Project {
+    /// Compose file to read (repeatable, like `docker compose -f`)
+    #[arg(short = 'f', long = "file", value_name = "FILE")]
+    files: Vec<PathBuf>,
+
+    /// Project name, defaults to the `name` in the compose file or the name
+    /// of its directory
+    #[arg(short = 'p', long = "project-name", value_name = "NAME")]
+    name: Option<String>,
+}
+
+pub fn run(cmd: ComposeCommand) -> Result<ColoredString, Error> {
+    match cmd {
+        ComposeCommand::Up { project, ip } => compose_up(&project, &ip),
+        ComposeCommand::Down { project } => compose_down(&project),
+    }
+}
+
+fn compose_up(project: &Project, ip: &str) -> Result<ColoredString, Error> {
+    let (name, documents) = load(project)?;
+
+    let mut hostnames: Vec<String> = Vec::new();
+    for hostname in documents.iter().flat_map(service_hostnames) {
+        if !hostnames.contains(&hostname) {
+            hostnames.push(hostname);
+        }
+    }
+
+    if hostnames.is_empty() {
+        return Err(Error::Generic(
+            format!(
+                "No hostnames found for compose project {}",
+                name.yellow().bold()
+            )
+            .red(),
+        ));
+    }
+
+    let entries: Vec<Entry> = hostnames
+        .iter()
+        .map(|hostname| Entry::new(ip, hostname))
+        .collect();
+    let written = update_hosts(|hosts| Ok(hosts.sync_block(&block_name(&name), entries)))?;
+
+    let mut lines = vec![format!(
+        "Added {} entries for compose project {}:",
+        written.len(),
+        name.yellow().bold()
+    )];
+    lines.extend(written.iter().map(|entry| {
+        format!(
+            "{} {}",
+            entry.ip.cyan().bold(),
+            entry.hostnames.join(" ").magenta().bold()
+        )
+    }));
+
+    Ok(lines.join("\n").green())
+}
+
+fn compose_down(project: &Project) -> Result<ColoredString, Error> {
+    let (name, _) = load(project)?;
+
+    update_hosts(|hosts| match hosts.remove_block(&block_name(&name)) {
+        Some(_) => Ok(()),
+        None => Err(Error::Generic(
+            format!("No entries for compose project {}", name.yellow().bold()).red(),
+        )),
+    })?;
+
+    Ok(format!(
+        "Removed entries for compose project {}",
+        name.yellow().bold()
+    )
+    .green())
+}
+
+/// Reads the project's compose files, returning its name and their contents.
+fn load(project: &Project) -> Result<(String, Vec<Value>), Error> {
+    let files = compose_files(project)?;
+    let documents = files
+        .iter()
+        .map(|file| Ok(serde_yaml::from_str(&fs::read_to_string(file)?)?))
+        .collect::<Result<Vec<Value>, Error>>()?;
+
+    Ok((project_name(project, &files, &documents), documents))
+}
+
+fn compose_files(project: &Project) -> Result<Vec<PathBuf>, Error> {
+    if !project.files.is_empty() {
+        return Ok(project.files.clone());
+    }
+
+    DEFAULT_FILES
+        .iter()
+        .map(PathBuf::from)
+        .find(|file| file.exists())
+        .map(|file| vec![file])
+        .ok_or_else(|| {
+            Error::Generic(
+                format!(
+                    "No compose file found, looked for {}",
+                    DEFAULT_FILES.join(", ")
+                )
+                .red(),
+            )
+        })
+}
+
+/// The project name the way `docker compose` picks it: explicit name, then
+/// the top-level `name`, then the directory of the first compose file.
+fn project_name(project: &Project, files: &[PathBuf], documents: &[Value]) -> String {
+    if let Some(name) = &project.name {
+        return name.clone();
+    }
+
+    if let Some(name) = documents.iter().rev().find_map(|doc| doc["name"].as_str()) {
+        return name.to_string();
+    }
+
+    files
+        .first()
+        .and_then(|file| fs::canonicalize(file).ok())
+        .as_deref()
+        .and_then(Path::parent)
+        .and_then(Path::file_name)
+        .map(|dir| dir.to_string_lossy().to_lowercase())
+        .unwrap_or_else(|| "default".to_string())
+}
+
+fn service_hostnames(document: &Value) -> Vec<String> {
+    let Some(services) = document["services"].as_mapping() else {
+        return Vec::new();
+    };
+
+    let mut hostnames = Vec::new();
+    for (name, service) in services {
+        hostnames.extend(name.as_str().map(String::from));
+        hostnames.extend(service["hostname"].as_str().map(String::from));
+
+        for (key, value) in key_values(&service["extra_hosts"], ':') {
+            // `host-gateway` is docker's alias for the host itself
+            if value.as_deref() != Some("host-gateway") {
+                hostnames.push(key);
+            }
+        }
+
+        for (key, value) in key_values(&service["labels"], '=') {
+            let Some(value) = value else { continue };
+            if key.starts_with("traefik.") && key.ends_with(".rule") {
+                hostnames.extend(traefik_hosts(&value));
+            } else if key == "VIRTUAL_HOST" {
+                hostnames.extend(split_list(&value));
+            }
+        }
+
+        for (key, value) in key_values(&service["environment"], '=') {
+            match value {
+                Some(value) if key == "VIRTUAL_HOST" => hostnames.extend(split_list(&value)),
+                _ => {}
+            }
+        }
+    }
+
+    hostnames.retain(|hostname| is_hostname(hostname));
+    hostnames
+}
+
+/// Reads a compose field that may be written either as a mapping or as a list
+/// of `key<separator>value` strings.
+fn key_values(value: &Value, separator: char) -> Vec<(String, Option<String>)> {
+    match value {
+        Value::Mapping(mapping) => mapping
+            .iter()
+            .filter_map(|(key, value)| {
+                let value = match value {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Number(n) => Some(n.to_string()),
+                    Value::Bool(b) => Some(b.to_string()),
+                    _ => None,
+                };
+                Some((key.as_str()?.to_string(), value))
+            })
+            .collect(),
+        Value::Sequence(items) => items
+            .iter()
+            .filter_map(Value::as_str)
+            .map(|item| match item.split_once(separator) {
+                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                None => (item.to_string(), None),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Hostnames from a Traefik rule such as
+/// ``Host(`app.test`) || Host(`www.app.test`, `old.app.test`)``.
+fn traefik_hosts(rule: &str) -> Vec<String> {
+    let mut hosts = Vec::new();
+    let mut rest = rule;
+
+    while let Some(start) = rest.find("Host(") {
+        rest = &rest[start + "Host(".len()..];
+        let end = rest.find(')').unwrap_or(rest.len());
+        hosts.extend(
+            rest[..end]
+                .split(',')
+                .map(|arg| {
+                    arg.trim()
+                        .trim_matches(|c| c == '`' || c == '"' || c == '\'')
+                })
+                .filter(|host| !host.is_empty())
+                .map(String::from),
+        );
+        rest = &rest[end..];
+    }
+
+    hosts
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn block_name(project: &str) -> String {
+    format!("compose:{project}")
+}
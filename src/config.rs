@@ -0,0 +1,162 @@
+//! Defaults from `config.toml`.
+//!
+//! hostie reads `/etc/hostie/config.toml` and then the user's
+//! `~/.config/hostie/config.toml`, whose settings win, e.g.
+//!
+//! ```toml
+//! hosts_file = "/etc/hosts"
+//! backup_dir = "/var/backups/hostie"
+//! color = "never"
+//! protected = ["localhost", "broadcasthost", "ip6-localhost"]
+//! block_begin = "# BEGIN hostie"
+//! block_end = "# END hostie"
+//! default_ip = "127.0.0.1"
+//! ```
+//!
+//! Both files are optional, as is every setting.
+
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+use toml::{Table, Value};
+
+use crate::Error;
+use crate::hosts::{BLOCK_BEGIN, BLOCK_END};
+use crate::paths;
+
+pub struct Config {
+    /// The hosts file to work on instead of the system's
+    pub hosts_file: Option<PathBuf>,
+    /// The config file `hosts_file` was set in
+    pub hosts_file_source: Option<PathBuf>,
+    /// Where to keep a copy of the hosts file before each change
+    pub backup_dir: Option<PathBuf>,
+    /// `Some(false)` to never color output, `Some(true)` to always do
+    pub color: Option<bool>,
+    /// Hostnames `remove` refuses to touch
+    pub protected: Vec<String>,
+    pub block_begin: String,
+    pub block_end: String,
+    /// The address `add` and `run` use when only a hostname is given
+    pub default_ip: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            hosts_file: None,
+            hosts_file_source: None,
+            backup_dir: None,
+            color: None,
+            protected: vec!["localhost".to_string(), "broadcasthost".to_string()],
+            block_begin: BLOCK_BEGIN.to_string(),
+            block_end: BLOCK_END.to_string(),
+            default_ip: "127.0.0.1".to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config files that exist, see `paths::config_paths`.
+    pub fn load() -> Result<Self, Error> {
+        let mut settings = Table::new();
+        let mut hosts_file_source = None;
+        for path in paths::config_paths() {
+            match fs::read_to_string(&path) {
+                Ok(contents) => {
+                    let table: Table = contents
+                        .parse()
+                        .map_err(|err| invalid(&path, format!("{err}")))?;
+                    if table.contains_key("hosts_file") {
+                        hosts_file_source = Some(path);
+                    }
+                    settings.extend(table);
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let mut config = Config {
+            hosts_file_source,
+            ..Config::default()
+        };
+        for (key, value) in settings {
+            match key.as_str() {
+                "hosts_file" => config.hosts_file = Some(string(&key, &value)?.into()),
+                "backup_dir" => config.backup_dir = Some(string(&key, &value)?.into()),
+                "color" => {
+                    config.color = match string(&key, &value)?.as_str() {
+                        "auto" => None,
+                        "always" => Some(true),
+                        "never" => Some(false),
+                        other => {
+                            return Err(setting_error(
+                                &key,
+                                format!(
+                                    "expected \"auto\", \"always\" or \"never\", got {other:?}"
+                                ),
+                            ));
+                        }
+                    }
+                }
+                "protected" => {
+                    config.protected = value
+                        .as_array()
+                        .and_then(|names| {
+                            names
+                                .iter()
+                                .map(|name| name.as_str().map(String::from))
+                                .collect()
+                        })
+                        .ok_or_else(|| setting_error(&key, "expected a list of hostnames"))?;
+                }
+                "block_begin" => config.block_begin = marker(&key, &value)?,
+                "block_end" => config.block_end = marker(&key, &value)?,
+                "default_ip" => {
+                    let ip = string(&key, &value)?;
+                    ip.parse::<IpAddr>()
+                        .map_err(|err| setting_error(&key, format!("{ip:?}: {err}")))?;
+                    config.default_ip = ip;
+                }
+                _ => return Err(setting_error(&key, "unknown setting")),
+            }
+        }
+
+        if config.block_begin == config.block_end {
+            return Err(setting_error("block_end", "must differ from block_begin"));
+        }
+
+        Ok(config)
+    }
+}
+
+fn string(key: &str, value: &Value) -> Result<String, Error> {
+    value
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| setting_error(key, "expected a string"))
+}
+
+/// A block marker, which has to stay a comment so other programs skip it.
+fn marker(key: &str, value: &Value) -> Result<String, Error> {
+    let marker = string(key, value)?.trim().to_string();
+    if !marker.starts_with('#') || marker.contains('\n') {
+        return Err(setting_error(
+            key,
+            "expected a single comment line starting with #",
+        ));
+    }
+    Ok(marker)
+}
+
+fn setting_error(key: &str, message: impl std::fmt::Display) -> Error {
+    Error::Generic(format!("Invalid config setting {}: {message}", key.yellow().bold()).red())
+}
+
+fn invalid(path: &Path, message: String) -> Error {
+    Error::Generic(format!("Invalid config file {}: {message}", path.display()).red())
+}
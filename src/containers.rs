@@ -0,0 +1,254 @@
+//! Entries for running containers, read from the Docker or Podman Engine API.
+//!
+//! Each container's name and network aliases are mapped to its address on
+//! that network, in a managed block that is rewritten on every sync. With
+//! `--follow`, the events stream is used to sync again whenever containers
+//! start, stop or change networks.
+
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use colored::{ColoredString, Colorize};
+use serde_json::Value;
+
+use crate::hosts::Entry;
+use crate::{Error, update_hosts};
+
+const BLOCK_NAME: &str = "containers";
+
+/// Length of the short container ID engines register as an alias.
+const SHORT_ID_LEN: usize = 12;
+
+/// Only the events that can change a container's name or addresses.
+const EVENTS_PATH: &str = "/events?filters=%7B%22type%22%3A%5B%22container%22%2C%22network%22%5D%2C%22event%22%3A%5B%22start%22%2C%22die%22%2C%22destroy%22%2C%22rename%22%2C%22connect%22%2C%22disconnect%22%5D%7D";
+
+#[derive(Parser)]
+pub enum ContainersCommand {
+    /// Map running containers' names and aliases to their IP addresses
+    Sync {
+        /// Path of the Engine API socket, defaults to `DOCKER_HOST` or the
+        /// standard Docker and Podman locations
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+
+        /// Keep running and sync again whenever containers change
+        #[arg(long)]
+        follow: bool,
+    },
+}
+
+pub fn run(cmd: ContainersCommand) -> Result<ColoredString, Error> {
+    match cmd {
+        ContainersCommand::Sync { socket, follow } => {
+            let socket = match socket {
+                Some(socket) => socket,
+                None => default_socket()?,
+            };
+            if follow {
+                follow_containers(&socket)
+            } else {
+                sync_containers(&socket)
+            }
+        }
+    }
+}
+
+fn sync_containers(socket: &Path) -> Result<ColoredString, Error> {
+    let response = request(socket, "/containers/json")?;
+    let containers: Value = serde_json::from_slice(&read_body(response)?)?;
+
+    let entries = container_entries(&containers);
+    let written = update_hosts(|hosts| Ok(hosts.sync_block(BLOCK_NAME, entries)))?;
+
+    let mut lines = vec![format!("Synced {} container entries", written.len())];
+    lines.extend(written.iter().map(|entry| {
+        format!(
+            "{} {}",
+            entry.ip.cyan().bold(),
+            entry.hostnames.join(" ").magenta().bold()
+        )
+    }));
+
+    Ok(lines.join("\n").green())
+}
+
+fn follow_containers(socket: &Path) -> Result<ColoredString, Error> {
+    println!("{}", sync_containers(socket)?);
+
+    let mut events = request(socket, EVENTS_PATH)?;
+    if !is_chunked(&read_headers(&mut events)?) {
+        return Err(Error::Generic(
+            "Unexpected response from the events endpoint".red(),
+        ));
+    }
+
+    // Each chunk carries one or more events; rather than interpret them, the
+    // container list is simply fetched again.
+    while read_chunk(&mut events)?.is_some() {
+        println!("{}", sync_containers(socket)?);
+    }
+
+    Ok("Container engine closed the events stream".green())
+}
+
+/// Builds one entry per container and network, e.g.
+/// `172.17.0.2 web-1 web` for a container named `web-1` with alias `web`.
+fn container_entries(containers: &Value) -> Vec<Entry> {
+    let mut entries = Vec::new();
+
+    for container in containers.as_array().into_iter().flatten() {
+        let id = container["Id"].as_str().unwrap_or_default();
+        let names: Vec<&str> = container["Names"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(|name| name.trim_start_matches('/'))
+            .collect();
+
+        let Some(networks) = container["NetworkSettings"]["Networks"].as_object() else {
+            continue;
+        };
+        let mut networks: Vec<_> = networks.iter().collect();
+        networks.sort_by_key(|(name, _)| *name);
+
+        for (_, network) in networks {
+            let ip = network["IPAddress"].as_str().unwrap_or_default();
+            if ip.is_empty() {
+                continue;
+            }
+
+            let aliases = ["Aliases", "DNSNames"]
+                .iter()
+                .flat_map(|key| network[key].as_array().into_iter().flatten())
+                .filter_map(Value::as_str);
+
+            let mut hostnames: Vec<String> = Vec::new();
+            for hostname in names.iter().copied().chain(aliases) {
+                // Engines also register the short container ID as an alias,
+                // but names like `db` or `cafe` can be an ID prefix too
+                let is_id = hostname.len() >= SHORT_ID_LEN
+                    && hostname.chars().all(|c| c.is_ascii_hexdigit())
+                    && id.starts_with(hostname);
+                if !hostname.is_empty() && !is_id && !hostnames.iter().any(|h| h == hostname) {
+                    hostnames.push(hostname.to_string());
+                }
+            }
+
+            if !hostnames.is_empty() {
+                let mut entry = Entry::new(ip, &hostnames[0]);
+                entry.hostnames = hostnames;
+                entries.push(entry);
+            }
+        }
+    }
+
+    entries
+}
+
+fn default_socket() -> Result<PathBuf, Error> {
+    if let Some(path) = env::var("DOCKER_HOST")
+        .ok()
+        .and_then(|host| host.strip_prefix("unix://").map(PathBuf::from))
+    {
+        return Ok(path);
+    }
+
+    let mut candidates = vec![PathBuf::from("/var/run/docker.sock")];
+    if let Ok(runtime_dir) = env::var("XDG_RUNTIME_DIR") {
+        candidates.push(Path::new(&runtime_dir).join("podman/podman.sock"));
+    }
+    candidates.push(PathBuf::from("/run/podman/podman.sock"));
+
+    candidates
+        .into_iter()
+        .find(|path| path.exists())
+        .ok_or_else(|| {
+            Error::Generic("No Docker or Podman socket found, pass one with --socket".red())
+        })
+}
+
+#[cfg(unix)]
+fn request(socket: &Path, path: &str) -> Result<BufReader<impl Read>, Error> {
+    let mut stream = std::os::unix::net::UnixStream::connect(socket)?;
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    )?;
+    Ok(BufReader::new(stream))
+}
+
+#[cfg(not(unix))]
+fn request(_socket: &Path, _path: &str) -> Result<BufReader<std::io::Empty>, Error> {
+    Err(Error::Generic(
+        "Syncing containers is only supported on Unix".red(),
+    ))
+}
+
+/// Reads the status line and headers, failing on anything but `200 OK`.
+fn read_headers(response: &mut impl BufRead) -> Result<Vec<(String, String)>, Error> {
+    let mut status = String::new();
+    response.read_line(&mut status)?;
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(Error::Generic(
+            format!("Container engine responded with: {}", status.trim()).red(),
+        ));
+    }
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if response.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    Ok(headers)
+}
+
+fn read_body(mut response: impl BufRead) -> Result<Vec<u8>, Error> {
+    let headers = read_headers(&mut response)?;
+
+    let mut body = Vec::new();
+    if is_chunked(&headers) {
+        while let Some(chunk) = read_chunk(&mut response)? {
+            body.extend(chunk);
+        }
+    } else {
+        response.read_to_end(&mut body)?;
+    }
+
+    Ok(body)
+}
+
+fn is_chunked(headers: &[(String, String)]) -> bool {
+    headers
+        .iter()
+        .any(|(name, value)| name == "transfer-encoding" && value.contains("chunked"))
+}
+
+/// Reads one chunk of a chunked body, or `None` at its end.
+fn read_chunk(response: &mut impl BufRead) -> Result<Option<Vec<u8>>, Error> {
+    let mut size = String::new();
+    if response.read_line(&mut size)? == 0 {
+        return Ok(None);
+    }
+    let size = size.trim().split(';').next().unwrap_or_default();
+    let size = usize::from_str_radix(size, 16)
+        .map_err(|_| Error::Generic(format!("Invalid chunk size: {size:?}").red()))?;
+    if size == 0 {
+        return Ok(None);
+    }
+
+    let mut chunk = vec![0; size];
+    response.read_exact(&mut chunk)?;
+    let mut crlf = [0; 2];
+    response.read_exact(&mut crlf)?;
+
+    Ok(Some(chunk))
+}
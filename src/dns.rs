@@ -0,0 +1,495 @@
+//! A small DNS server answering from the hosts file.
+//!
+//! A, AAAA and PTR queries for names in the hosts file, or matching a wildcard
+//! rule, are answered directly. Wildcard rules come from `--wildcard` and from
+//! hosts file entries such as `127.0.0.1 *.myapp.test`, which resolvers that
+//! read the file themselves ignore. Anything else is forwarded to an upstream
+//! server if one is given, and refused otherwise.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use clap::Parser;
+use colored::{ColoredString, Colorize};
+
+use crate::hosts::HostsFile;
+use crate::{Error, get_hosts_path};
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+const RCODE_SERVFAIL: u16 = 2;
+const RCODE_NOTIMP: u16 = 4;
+const RCODE_REFUSED: u16 = 5;
+
+/// Kept short, since the hosts file can change at any moment.
+const ANSWER_TTL: u32 = 5;
+/// The largest response that fits a plain UDP message.
+const MAX_RESPONSE: usize = 512;
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+/// Forwarded queries waiting for an answer at most, beyond which further
+/// ones fail right away.
+const MAX_PENDING: usize = 1024;
+
+#[derive(Parser)]
+pub struct ServeDns {
+    /// Address to listen on
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:5353")]
+    listen: SocketAddr,
+
+    /// Answer for every subdomain of a domain, e.g. `*.myapp.test=127.0.0.1`
+    /// (repeatable)
+    #[arg(long = "wildcard", value_name = "PATTERN=IP", value_parser = parse_wildcard)]
+    wildcards: Vec<(String, IpAddr)>,
+
+    /// Forward queries for other names to this server, e.g. `1.1.1.1:53`,
+    /// instead of refusing them
+    #[arg(long, value_name = "ADDR")]
+    upstream: Option<SocketAddr>,
+}
+
+pub fn serve_dns(serve: &ServeDns) -> Result<ColoredString, Error> {
+    let socket = UdpSocket::bind(serve.listen)?;
+    let path = get_hosts_path();
+
+    println!(
+        "{}",
+        format!(
+            "Listening for DNS queries on {} (Ctrl-C to stop)",
+            socket.local_addr()?
+        )
+        .green()
+    );
+
+    let forwarder = match serve.upstream {
+        Some(upstream) => Some(Forwarder::start(upstream, socket.try_clone()?)?),
+        None => None,
+    };
+
+    let mut records = CachedRecords::default();
+    let mut buf = [0; 1500];
+    loop {
+        // A client that went away, or an ICMP error for an earlier response,
+        // mustn't stop the server
+        let (len, client) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(err) => {
+                log_error("receiving a query", &err);
+                continue;
+            }
+        };
+        let Some(query) = Query::parse(&buf[..len]) else {
+            continue;
+        };
+
+        let response = match records.get(&path, &serve.wildcards).answer(&query) {
+            Some(response) => response,
+            None => match &forwarder {
+                Some(forwarder) => match forwarder.forward(&buf[..len], client) {
+                    Ok(()) => continue,
+                    Err(err) => {
+                        log_error("forwarding a query", &err);
+                        query.response(RCODE_SERVFAIL, &[])
+                    }
+                },
+                None => query.response(RCODE_REFUSED, &[]),
+            },
+        };
+
+        if let Err(err) = socket.send_to(&response, client) {
+            log_error("answering a query", &err);
+        }
+    }
+}
+
+fn log_error(doing: &str, err: &io::Error) {
+    let timestamp = humantime::format_rfc3339_seconds(SystemTime::now());
+    eprintln!("{timestamp} {}", format!("Error {doing}: {err}").red());
+}
+
+fn parse_wildcard(value: &str) -> Result<(String, IpAddr), String> {
+    let (pattern, ip) = value
+        .split_once('=')
+        .ok_or("expected PATTERN=IP, e.g. *.myapp.test=127.0.0.1")?;
+    let suffix = pattern
+        .strip_prefix('*')
+        .filter(|suffix| suffix.starts_with('.'))
+        .ok_or("wildcard patterns look like *.myapp.test")?;
+    let ip = ip
+        .parse()
+        .map_err(|err| format!("invalid IP address: {err}"))?;
+    Ok((suffix.to_lowercase(), ip))
+}
+
+/// Forwards queries to the upstream server over a single socket, telling
+/// the responses apart by the message ID, which is replaced by one of ours
+/// since different clients may use the same.
+struct Forwarder {
+    upstream: SocketAddr,
+    socket: UdpSocket,
+    pending: Arc<Mutex<Pending>>,
+}
+
+#[derive(Default)]
+struct Pending {
+    next_id: u16,
+    /// The client and its ID for each of our IDs
+    queries: HashMap<u16, (SocketAddr, [u8; 2], Instant)>,
+}
+
+impl Forwarder {
+    /// Binds the upstream socket and starts passing responses on to clients
+    /// through `replies`, the socket they sent their queries to.
+    fn start(upstream: SocketAddr, replies: UdpSocket) -> io::Result<Self> {
+        let bind: SocketAddr = match upstream {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(bind)?;
+
+        // Hard to guess for anyone trying to slip in forged responses
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let pending = Arc::new(Mutex::new(Pending {
+            next_id: seed as u16,
+            queries: HashMap::new(),
+        }));
+
+        let responses = socket.try_clone()?;
+        let waiting = pending.clone();
+        thread::spawn(move || relay_responses(upstream, &responses, &replies, &waiting));
+
+        Ok(Forwarder {
+            upstream,
+            socket,
+            pending,
+        })
+    }
+
+    fn forward(&self, message: &[u8], client: SocketAddr) -> io::Result<()> {
+        let id = {
+            let mut pending = self.pending.lock().unwrap_or_else(|p| p.into_inner());
+            let now = Instant::now();
+            pending
+                .queries
+                .retain(|_, (_, _, sent)| now.duration_since(*sent) < UPSTREAM_TIMEOUT);
+            if pending.queries.len() >= MAX_PENDING {
+                return Err(io::Error::other("too many queries waiting for upstream"));
+            }
+
+            let mut id = pending.next_id;
+            while pending.queries.contains_key(&id) {
+                id = id.wrapping_add(1);
+            }
+            pending.next_id = id.wrapping_add(1);
+            pending
+                .queries
+                .insert(id, (client, [message[0], message[1]], now));
+            id
+        };
+
+        let mut message = message.to_vec();
+        message[..2].copy_from_slice(&id.to_be_bytes());
+        self.socket.send_to(&message, self.upstream)?;
+        Ok(())
+    }
+}
+
+fn relay_responses(
+    upstream: SocketAddr,
+    responses: &UdpSocket,
+    replies: &UdpSocket,
+    pending: &Mutex<Pending>,
+) {
+    let mut buf = vec![0; 4096];
+    loop {
+        let len = match responses.recv_from(&mut buf) {
+            Ok((len, from)) if from == upstream && len >= 2 => len,
+            Ok(_) => continue,
+            Err(err) => {
+                log_error("receiving from upstream", &err);
+                continue;
+            }
+        };
+
+        let id = u16::from_be_bytes([buf[0], buf[1]]);
+        let query = pending
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .queries
+            .remove(&id);
+        // Late, or not an answer to anything we asked
+        let Some((client, client_id, _)) = query else {
+            continue;
+        };
+
+        buf[..2].copy_from_slice(&client_id);
+        if let Err(err) = replies.send_to(&buf[..len], client) {
+            log_error("answering a query", &err);
+        }
+    }
+}
+
+/// The records from the hosts file, reloaded whenever it is modified.
+#[derive(Default)]
+struct CachedRecords {
+    modified: Option<SystemTime>,
+    records: Records,
+}
+
+impl CachedRecords {
+    fn get(&mut self, path: &str, wildcards: &[(String, IpAddr)]) -> &Records {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if modified.is_none() || modified != self.modified {
+            self.records = Records::load(path, wildcards);
+            self.modified = modified;
+        }
+        &self.records
+    }
+}
+
+#[derive(Default)]
+struct Records {
+    addresses: HashMap<String, Vec<IpAddr>>,
+    /// `(".myapp.test", ip)` for `*.myapp.test`
+    wildcards: Vec<(String, IpAddr)>,
+    names: HashMap<IpAddr, Vec<String>>,
+}
+
+impl Records {
+    fn load(path: &str, wildcards: &[(String, IpAddr)]) -> Self {
+        let mut records = Records {
+            wildcards: wildcards.to_vec(),
+            ..Records::default()
+        };
+
+        // A missing or unreadable file just means there's nothing to answer
+        let hosts = HostsFile::read(path).unwrap_or_default();
+        let now = SystemTime::now();
+        for entry in hosts.entries().filter(|entry| !entry.is_expired(now)) {
+            let Ok(ip) = entry.ip.parse::<IpAddr>() else {
+                continue;
+            };
+            for hostname in &entry.hostnames {
+                let hostname = hostname.to_lowercase();
+                if let Some(suffix) = hostname.strip_prefix('*') {
+                    records.wildcards.push((suffix.to_string(), ip));
+                    continue;
+                }
+                records.names.entry(ip).or_default().push(hostname.clone());
+                let addresses = records.addresses.entry(hostname).or_default();
+                if !addresses.contains(&ip) {
+                    addresses.push(ip);
+                }
+            }
+        }
+
+        records
+    }
+
+    /// Addresses for `name`, or `None` if it isn't one of ours. Exact names
+    /// win over wildcards, and more specific wildcards over broader ones.
+    fn lookup(&self, name: &str) -> Option<Vec<IpAddr>> {
+        if let Some(addresses) = self.addresses.get(name) {
+            return Some(addresses.clone());
+        }
+
+        let longest = self
+            .wildcards
+            .iter()
+            .filter(|(suffix, _)| name.ends_with(suffix.as_str()))
+            .map(|(suffix, _)| suffix.len())
+            .max()?;
+        Some(
+            self.wildcards
+                .iter()
+                .filter(|(suffix, _)| suffix.len() == longest && name.ends_with(suffix.as_str()))
+                .map(|(_, ip)| *ip)
+                .collect(),
+        )
+    }
+
+    /// The response to `query`, or `None` if it should go upstream.
+    fn answer(&self, query: &Query) -> Option<Vec<u8>> {
+        if query.opcode() != 0 {
+            return Some(query.response(RCODE_NOTIMP, &[]));
+        }
+        if query.qclass != CLASS_IN {
+            return None;
+        }
+
+        let name = query.name.to_lowercase();
+        let answers: Vec<RData> = match query.qtype {
+            TYPE_A | TYPE_AAAA => {
+                let addresses = self.lookup(&name)?;
+                addresses
+                    .into_iter()
+                    .filter_map(|ip| match (ip, query.qtype) {
+                        (IpAddr::V4(ip), TYPE_A) => Some(RData::A(ip)),
+                        (IpAddr::V6(ip), TYPE_AAAA) => Some(RData::Aaaa(ip)),
+                        _ => None,
+                    })
+                    .collect()
+            }
+            TYPE_PTR => {
+                let names = self.names.get(&reverse_name_to_ip(&name)?)?;
+                names.iter().cloned().map(RData::Ptr).collect()
+            }
+            // Other types for our names exist, but have no data
+            _ => {
+                self.lookup(&name)?;
+                Vec::new()
+            }
+        };
+
+        Some(query.response(0, &answers))
+    }
+}
+
+/// `4.3.2.1.in-addr.arpa` to `1.2.3.4`, and likewise for `ip6.arpa` names.
+fn reverse_name_to_ip(name: &str) -> Option<IpAddr> {
+    if let Some(octets) = name.strip_suffix(".in-addr.arpa") {
+        let mut octets: Vec<u8> = octets
+            .split('.')
+            .map(|octet| octet.parse().ok())
+            .collect::<Option<_>>()?;
+        octets.reverse();
+        let octets: [u8; 4] = octets.try_into().ok()?;
+        return Some(IpAddr::from(octets));
+    }
+
+    let nibbles = name.strip_suffix(".ip6.arpa")?;
+    let mut nibbles: Vec<u8> = nibbles
+        .split('.')
+        .map(|nibble| u8::from_str_radix(nibble, 16).ok().filter(|n| *n < 16))
+        .collect::<Option<_>>()?;
+    if nibbles.len() != 32 {
+        return None;
+    }
+    nibbles.reverse();
+    let octets: Vec<u8> = nibbles
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect();
+    let octets: [u8; 16] = octets.try_into().ok()?;
+    Some(IpAddr::from(octets))
+}
+
+enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+}
+
+struct Query {
+    id: u16,
+    flags: u16,
+    name: String,
+    qtype: u16,
+    qclass: u16,
+    /// The question section as received, echoed back in the response
+    question: Vec<u8>,
+}
+
+impl Query {
+    /// Parses a query with a single question, or `None` for anything we
+    /// can't make sense of (those are dropped, like most servers do).
+    fn parse(message: &[u8]) -> Option<Self> {
+        let header = message.get(..12)?;
+        let id = u16::from_be_bytes([header[0], header[1]]);
+        let flags = u16::from_be_bytes([header[2], header[3]]);
+        let qdcount = u16::from_be_bytes([header[4], header[5]]);
+        if flags & 0x8000 != 0 || qdcount != 1 {
+            return None;
+        }
+
+        let mut labels = Vec::new();
+        let mut pos = 12;
+        loop {
+            let len = usize::from(*message.get(pos)?);
+            pos += 1;
+            if len == 0 {
+                break;
+            }
+            // Compression pointers have no business in a question
+            if len > 63 {
+                return None;
+            }
+            labels.push(String::from_utf8_lossy(message.get(pos..pos + len)?).into_owned());
+            pos += len;
+        }
+
+        let fixed = message.get(pos..pos + 4)?;
+        Some(Query {
+            id,
+            flags,
+            name: labels.join("."),
+            qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+            qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+            question: message[12..pos + 4].to_vec(),
+        })
+    }
+
+    fn opcode(&self) -> u16 {
+        (self.flags >> 11) & 0xf
+    }
+
+    /// An authoritative response echoing the query's opcode and RD bit.
+    fn response(&self, rcode: u16, answers: &[RData]) -> Vec<u8> {
+        let mut flags = 0x8000 | (self.flags & 0x7800) | 0x0400 | (self.flags & 0x0100) | rcode;
+
+        let mut records = Vec::new();
+        let mut count: u16 = 0;
+        for answer in answers {
+            let mut record = vec![0xc0, 12]; // pointer to the question's name
+            let (rtype, rdata) = match answer {
+                RData::A(ip) => (TYPE_A, ip.octets().to_vec()),
+                RData::Aaaa(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+                RData::Ptr(name) => (TYPE_PTR, encode_name(name)),
+            };
+            record.extend(rtype.to_be_bytes());
+            record.extend(CLASS_IN.to_be_bytes());
+            record.extend(ANSWER_TTL.to_be_bytes());
+            record.extend((rdata.len() as u16).to_be_bytes());
+            record.extend(rdata);
+
+            if 12 + self.question.len() + records.len() + record.len() > MAX_RESPONSE {
+                flags |= 0x0200; // truncated
+                break;
+            }
+            records.extend(record);
+            count += 1;
+        }
+
+        let mut message = Vec::with_capacity(12 + self.question.len() + records.len());
+        message.extend(self.id.to_be_bytes());
+        message.extend(flags.to_be_bytes());
+        message.extend(1u16.to_be_bytes());
+        message.extend(count.to_be_bytes());
+        message.extend(0u16.to_be_bytes());
+        message.extend(0u16.to_be_bytes());
+        message.extend(&self.question);
+        message.extend(records);
+        message
+    }
+}
+
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        encoded.push(label.len() as u8);
+        encoded.extend(label);
+    }
+    encoded.push(0);
+    encoded
+}
@@ -0,0 +1,188 @@
+//! Editing the hosts file by hand, safely, like `visudo` does for sudoers.
+//!
+//! The editor works on a copy. Only once the copy passes the checks below, or
+//! you insist, does it replace the hosts file, by renaming a new file over it
+//! so that no program ever reads it half-written, see `file::replace`.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::process::{self, Command};
+
+use colored::{ColoredString, Colorize};
+
+use crate::hosts::{HostsFile, is_hostname};
+use crate::{Error, blocklist, config, get_hosts_path, update_hosts};
+
+/// The editor used when neither `$VISUAL` nor `$EDITOR` is set.
+const DEFAULT_EDITOR: &str = if cfg!(windows) { "notepad" } else { "vi" };
+
+pub fn edit_hosts_file() -> Result<ColoredString, Error> {
+    let path = get_hosts_path();
+    let original = fs::read_to_string(&path)?;
+
+    // Named `hosts` so editors recognize the format. The directory must be
+    // new and private, as hostie usually runs as root in a shared /tmp.
+    let dir = env::temp_dir().join(format!("hostie-edit-{}", process::id()));
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(&dir)?;
+    let copy = dir.join("hosts");
+    fs::write(&copy, &original)?;
+
+    let result = edit_copy(&path, &original, &copy);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+fn edit_copy(path: &str, original: &str, copy: &Path) -> Result<ColoredString, Error> {
+    let edited = loop {
+        run_editor(copy)?;
+        let edited = fs::read_to_string(copy)?;
+        if edited == original {
+            return Ok("No changes made to hosts file".green());
+        }
+
+        let problems = lint(original, &edited);
+        if problems.is_empty() {
+            break edited;
+        }
+
+        eprintln!("{}", "Problems in the edited hosts file:".red());
+        for problem in &problems {
+            eprintln!("  {problem}");
+        }
+        match prompt()? {
+            Choice::Edit => continue,
+            Choice::Exit => return Ok("Hosts file left unchanged".yellow()),
+            Choice::Save => break edited,
+        }
+    };
+
+    update_hosts(|hosts| {
+        // Someone else's changes in the meantime would silently be lost
+        if hosts.to_string() != HostsFile::parse(original).to_string() {
+            return Err(Error::Generic(
+                format!("{path} changed while you were editing it, nothing was written").red(),
+            ));
+        }
+        *hosts = HostsFile::parse(&edited);
+        Ok(())
+    })?;
+
+    Ok(format!("Saved hosts file: {path}").green())
+}
+
+fn run_editor(file: &Path) -> Result<(), Error> {
+    let editor = env::var("VISUAL")
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .or_else(|| env::var("EDITOR").ok())
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string());
+
+    // Editors are often given with arguments, e.g. `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or(DEFAULT_EDITOR);
+    let status = Command::new(program)
+        .args(words)
+        .arg(file)
+        .status()
+        .map_err(|err| {
+            Error::Generic(format!("Could not start {}: {err}", program.yellow().bold()).red())
+        })?;
+
+    if !status.success() {
+        return Err(Error::Generic(
+            format!("{program} exited with {status}, hosts file left unchanged").red(),
+        ));
+    }
+    Ok(())
+}
+
+enum Choice {
+    Edit,
+    Exit,
+    Save,
+}
+
+fn prompt() -> Result<Choice, Error> {
+    loop {
+        eprint!("What now? Edit again (e), exit without saving (x), save anyway (Q): ");
+        io::stderr().flush()?;
+
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Ok(Choice::Exit);
+        }
+        match answer.trim() {
+            "e" => return Ok(Choice::Edit),
+            "x" => return Ok(Choice::Exit),
+            "Q" => return Ok(Choice::Save),
+            _ => {}
+        }
+    }
+}
+
+/// Everything in `edited` that would make it a broken hosts file, or drop an
+/// entry `original` had for a protected hostname.
+fn lint(original: &str, edited: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    // The first line each hostname is on, per address family
+    let mut seen: HashMap<(bool, &str), usize> = HashMap::new();
+    // Blocklists are hostie's to keep apart, and older versions let them overlap
+    let parsed = HostsFile::parse(edited);
+    let blocked: Vec<bool> = parsed
+        .lines_by_block()
+        .map(|(block, _)| blocklist::is_blocklist_block(block))
+        .collect();
+
+    for (number, line) in edited.lines().enumerate().map(|(i, line)| (i + 1, line)) {
+        let data = line.split('#').next().unwrap_or_default();
+        let mut fields = data.split_whitespace();
+        let Some(ip) = fields.next() else {
+            continue;
+        };
+
+        let Ok(address) = ip.parse::<IpAddr>() else {
+            problems.push(format!("line {number}: not an IP address: {ip}"));
+            continue;
+        };
+        let hostnames: Vec<&str> = fields.collect();
+        if hostnames.is_empty() {
+            problems.push(format!("line {number}: no hostname after {ip}"));
+        }
+
+        for hostname in hostnames {
+            // `*.example.test` is a wildcard for `serve-dns` and the NSS module
+            if !is_hostname(hostname.strip_prefix("*.").unwrap_or(hostname)) {
+                problems.push(format!("line {number}: not a valid hostname: {hostname}"));
+            } else if blocked[number - 1] {
+                continue;
+            } else if let Entry::Occupied(first) = seen.entry((address.is_ipv6(), hostname)) {
+                problems.push(format!(
+                    "line {number}: {hostname} already has an {} address on line {}",
+                    if address.is_ipv6() { "IPv6" } else { "IPv4" },
+                    first.get()
+                ));
+            } else {
+                seen.insert((address.is_ipv6(), hostname), number);
+            }
+        }
+    }
+
+    let (original, edited) = (HostsFile::parse(original), parsed);
+    for hostname in &config().protected {
+        let had = original.entries().any(|entry| entry.has_hostname(hostname));
+        if had && !edited.entries().any(|entry| entry.has_hostname(hostname)) {
+            problems.push(format!("the protected entry for {hostname} is gone"));
+        }
+    }
+
+    problems
+}
@@ -0,0 +1,234 @@
+//! Keeping a set of entries in place while other programs rewrite the file.
+//!
+//! The entries from the manifest live in a managed block. Whenever the hosts
+//! file changes, the block is restored if it went missing or was altered, and
+//! hostnames elsewhere that are mapped to other addresses are removed from
+//! their entries, since whichever comes first in the file wins.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use clap::Parser;
+use colored::{ColoredString, Colorize};
+
+use crate::hosts::{Entry, HostsFile, Line};
+use crate::{Error, config, get_hosts_path, update_hosts, watch};
+
+const BLOCK_NAME: &str = "enforce";
+
+/// Delay before the second of two corrections in quick succession, doubling
+/// for every further one up to `MAX_BACKOFF`.
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// How long the file has to stay correct before the backoff is reset.
+const QUIET_PERIOD: Duration = Duration::from_secs(60);
+/// Time for another program to finish writing the file, e.g. after
+/// truncating it, before it is read.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+#[derive(Parser)]
+pub struct Enforce {
+    /// Hosts-format file with the entries to keep in place
+    #[arg(long, value_name = "PATH")]
+    manifest: PathBuf,
+
+    /// Apply the manifest once and exit instead of watching for changes
+    #[arg(long)]
+    once: bool,
+}
+
+pub fn enforce_entries(enforce: &Enforce) -> Result<ColoredString, Error> {
+    let path = get_hosts_path();
+    let manifest: Vec<Entry> = HostsFile::read(&enforce.manifest)?
+        .entries()
+        .cloned()
+        .collect();
+
+    if manifest.is_empty() {
+        return Err(Error::Generic(
+            format!("No entries in manifest: {}", enforce.manifest.display()).red(),
+        ));
+    }
+
+    if enforce.once {
+        let corrections = apply(&manifest)?;
+        log(&corrections);
+        return Ok(format!("Enforced {} entries", manifest.len()).green());
+    }
+
+    let mut backoff = Backoff::default();
+
+    watch::on_change(
+        Path::new(&path),
+        || {
+            println!(
+                "{}",
+                format!(
+                    "Enforcing {} entries in {path} (Ctrl-C to stop)",
+                    manifest.len()
+                )
+                .green()
+            );
+            log(&apply(&manifest)?);
+            Ok(())
+        },
+        || {
+            thread::sleep(SETTLE_TIME);
+            let corrections = apply(&manifest)?;
+            if !corrections.is_empty() {
+                log(&corrections);
+                backoff.wait();
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok("Stopped enforcing entries".green())
+}
+
+/// Brings the hosts file in line with the manifest, returning a description
+/// of each correction that was needed.
+fn apply(manifest: &[Entry]) -> Result<Vec<String>, Error> {
+    // Whatever else is rewriting the file, at least other hostie processes
+    // wait for us
+    let changes = update_hosts(|hosts| {
+        let before = hosts.to_string();
+
+        let previous: Vec<Entry> = hosts
+            .remove_block(BLOCK_NAME)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|line| match line {
+                Line::Entry(entry) => Some(entry),
+                Line::Other(_) => None,
+            })
+            .collect();
+
+        let conflicting = remove_conflicts(hosts, manifest);
+
+        hosts.set_block(
+            BLOCK_NAME,
+            manifest.iter().cloned().map(Line::Entry).collect(),
+        );
+
+        Ok((hosts.to_string() != before).then_some((previous, conflicting)))
+    })?;
+    let Some((previous, conflicting)) = changes else {
+        return Ok(Vec::new());
+    };
+
+    let previous: HashSet<String> = previous.iter().map(ToString::to_string).collect();
+    let wanted: HashSet<String> = manifest.iter().map(ToString::to_string).collect();
+
+    let mut corrections: Vec<String> = manifest
+        .iter()
+        .filter(|entry| !previous.contains(&entry.to_string()))
+        .map(|entry| format!("restored {}", describe(entry)))
+        .collect();
+    corrections.extend(
+        conflicting
+            .iter()
+            .map(|entry| format!("removed conflicting {}", describe(entry))),
+    );
+    // Anything else is the block having been moved or reformatted
+    if corrections.is_empty() || previous.iter().any(|line| !wanted.contains(line)) {
+        corrections.push("rewrote managed block".to_string());
+    }
+
+    Ok(corrections)
+}
+
+/// Removes the hostnames from the manifest that entries outside the managed
+/// block map to a different address of the same family, dropping entries
+/// left without a hostname. Returns what was removed, as entries.
+fn remove_conflicts(hosts: &mut HostsFile, manifest: &[Entry]) -> Vec<Entry> {
+    let mut removed = Vec::new();
+    hosts.lines_mut().retain_mut(|line| {
+        let Line::Entry(entry) = line else {
+            return true;
+        };
+        let (conflicting, kept): (Vec<String>, Vec<String>) = entry
+            .hostnames
+            .iter()
+            .cloned()
+            .partition(|hostname| conflicts(entry, hostname, manifest));
+        if conflicting.is_empty() {
+            return true;
+        }
+
+        let mut conflict = entry.clone();
+        conflict.hostnames = conflicting;
+        removed.push(conflict);
+        entry.hostnames = kept;
+        !entry.hostnames.is_empty()
+    });
+    removed
+}
+
+/// Whether `entry` maps `hostname`, from the manifest, to a different address
+/// of the same family.
+fn conflicts(entry: &Entry, hostname: &str, manifest: &[Entry]) -> bool {
+    !config()
+        .protected
+        .iter()
+        .any(|protected| protected == hostname)
+        && manifest.iter().any(|wanted| {
+            entry.ip != wanted.ip
+                && entry.is_ipv6() == wanted.is_ipv6()
+                && wanted.has_hostname(hostname)
+        })
+}
+
+fn describe(entry: &Entry) -> String {
+    format!(
+        "{} {}",
+        entry.ip.cyan().bold(),
+        entry.hostnames.join(" ").magenta().bold()
+    )
+}
+
+fn log(corrections: &[String]) {
+    let timestamp = humantime::format_rfc3339_seconds(SystemTime::now());
+    for correction in corrections {
+        println!("{timestamp} {correction}");
+    }
+}
+
+/// Slows down corrections that keep being undone, so that hostie doesn't get
+/// into a tight loop with another program rewriting the same file.
+#[derive(Default)]
+struct Backoff {
+    last_correction: Option<Instant>,
+    delay: Option<Duration>,
+}
+
+impl Backoff {
+    fn wait(&mut self) {
+        let now = Instant::now();
+        let recent = self
+            .last_correction
+            .is_some_and(|last| now.duration_since(last) < QUIET_PERIOD);
+
+        self.delay = match (recent, self.delay) {
+            (false, _) => None,
+            (true, None) => Some(BASE_BACKOFF),
+            (true, Some(delay)) => Some((delay * 2).min(MAX_BACKOFF)),
+        };
+
+        if let Some(delay) = self.delay {
+            println!(
+                "{}",
+                format!(
+                    "Entries keep being changed, waiting {} before the next correction",
+                    humantime::format_duration(delay)
+                )
+                .yellow()
+            );
+            thread::sleep(delay);
+        }
+
+        self.last_correction = Some(Instant::now());
+    }
+}
@@ -0,0 +1,130 @@
+//! Changing files that other programs read, and write, at the same time.
+//!
+//! Resolvers must never see a half-written hosts file, so it is replaced by
+//! renaming a new file over it. Where that's impossible, e.g. for a hosts file
+//! bind-mounted into a container, it is overwritten in place instead.
+//!
+//! Read-modify-write cycles take an advisory lock first, so that two hostie
+//! processes changing the same file don't undo each other's changes.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Replaces the file at `path` with `contents` by renaming a new file over
+/// it, falling back to overwriting it. A missing file is created.
+pub fn replace(path: impl AsRef<Path>, contents: &str) -> io::Result<()> {
+    // Replace the file a symlink points to rather than the symlink
+    let path = match fs::canonicalize(path.as_ref()) {
+        Ok(path) => path,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return fs::write(path, contents);
+        }
+        Err(err) => return Err(err),
+    };
+    let new = sibling(&path, &process::id().to_string());
+
+    let renamed = write_like(&path, &new, contents).and_then(|()| fs::rename(&new, &path));
+    if renamed.is_err() {
+        // E.g. EBUSY for a bind mount, or no write access to the directory
+        let _ = fs::remove_file(&new);
+        fs::write(&path, contents)?;
+    }
+    Ok(())
+}
+
+/// A hidden file next to `path`, e.g. `/etc/.hosts.hostie-lock`, so that
+/// renaming it stays on one filesystem.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.hostie-{suffix}"))
+}
+
+/// Writes `contents` to `new` with the permissions and owner of `path`.
+fn write_like(path: &Path, new: &Path, contents: &str) -> io::Result<()> {
+    let metadata = fs::metadata(path)?;
+    fs::write(new, contents)?;
+    fs::set_permissions(new, metadata.permissions())?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        std::os::unix::fs::chown(new, Some(metadata.uid()), Some(metadata.gid()))?;
+    }
+
+    Ok(())
+}
+
+/// An advisory lock on changing a file, held until dropped.
+#[must_use = "the lock is released as soon as it is dropped"]
+pub struct Lock {
+    /// The lock file, if one could be created
+    file: Option<(PathBuf, File)>,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // Removed while still locked, see `lock`
+        if let Some((path, _)) = &self.file {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Waits until no other process holds the lock for the file at `path`, then
+/// takes it.
+///
+/// The lock is a separate file next to it, as the file itself is replaced by
+/// a new one on every write. Where no lock file can be created, e.g. in a
+/// read-only directory, the returned lock doesn't exclude anyone.
+pub fn lock(path: impl AsRef<Path>) -> io::Result<Lock> {
+    let path = fs::canonicalize(path.as_ref()).unwrap_or_else(|_| path.as_ref().to_path_buf());
+    let lock_path = sibling(&path, "lock");
+
+    loop {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(false);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_NOFOLLOW);
+
+        let file = match options.open(&lock_path) {
+            Ok(file) => file,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem
+                ) =>
+            {
+                return Ok(Lock { file: None });
+            }
+            Err(err) => return Err(err),
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            use std::os::unix::io::AsRawFd;
+
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // The previous holder removes the lock file before releasing it,
+            // so whoever opened it meanwhile has to start over with a new one
+            let locked = file.metadata()?;
+            let current = fs::metadata(&lock_path);
+            if !current
+                .is_ok_and(|current| current.dev() == locked.dev() && current.ino() == locked.ino())
+            {
+                continue;
+            }
+        }
+
+        return Ok(Lock {
+            file: Some((lock_path, file)),
+        });
+    }
+}
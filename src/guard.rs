@@ -0,0 +1,97 @@
+//! Temporary entries for tests.
+//!
+//! ```no_run
+//! use hostie::guard::HostsGuard;
+//!
+//! let _guard = HostsGuard::add(&[("127.0.0.1", "svc.test")])?;
+//! // svc.test resolves to 127.0.0.1 until `_guard` is dropped
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Entries are marked with the process, like those of `hostie run`, so that
+//! if it is killed before the guard is dropped, the next `hostie` invocation
+//! removes them, see `owner`.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::hosts::{Entry, HostsFile};
+use crate::owner::Owner;
+use crate::paths::hosts_path;
+
+/// Serializes the read-modify-write cycles of guards in different threads,
+/// such as tests running in parallel, even where `file::lock` can't.
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Entries that are removed from the hosts file again when dropped.
+#[must_use = "the entries are removed as soon as the guard is dropped"]
+pub struct HostsGuard {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl HostsGuard {
+    /// Adds `(ip, hostname)` entries to the hosts file, which is
+    /// `$HOSTIE_HOSTS_FILE` if set, like for the `hostie` command.
+    pub fn add(entries: &[(&str, &str)]) -> io::Result<Self> {
+        Self::add_to(hosts_path(), entries)
+    }
+
+    /// Adds `(ip, hostname)` entries to the hosts file at `path`.
+    ///
+    /// Fails without changing anything if a hostname already has an entry.
+    pub fn add_to(path: impl AsRef<Path>, entries: &[(&str, &str)]) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let owner = Owner::current();
+        let entries: Vec<Entry> = entries
+            .iter()
+            .map(|(ip, hostname)| {
+                let mut entry = Entry::new(ip, hostname);
+                entry.run = Some(owner);
+                entry
+            })
+            .collect();
+
+        let _lock = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Other processes, such as separate test binaries and `hostie`
+        // commands, wait for the file lock
+        HostsFile::update(&path, |hosts| {
+            for entry in &entries {
+                if hosts
+                    .entries()
+                    .any(|e| e.has_hostname(&entry.hostnames[0]) && e.is_ipv6() == entry.is_ipv6())
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} already has an entry", entry.hostnames[0]),
+                    ));
+                }
+            }
+            for entry in &entries {
+                hosts.push(entry.clone());
+            }
+            Ok(())
+        })?;
+
+        Ok(HostsGuard { path, entries })
+    }
+}
+
+impl Drop for HostsGuard {
+    fn drop(&mut self) {
+        let _lock = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Nothing sensible to do about errors while dropping, and whatever is
+        // left behind is pruned by the next `hostie` invocation
+        let _ = HostsFile::update(&self.path, |hosts| {
+            hosts.remove_entries(|entry| {
+                !self.entries.iter().any(|ours| {
+                    entry.ip == ours.ip
+                        && entry.hostnames == ours.hostnames
+                        && entry.run == ours.run
+                })
+            });
+            Ok::<_, io::Error>(())
+        });
+    }
+}
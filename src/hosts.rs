@@ -0,0 +1,446 @@
+//! Parsing and rendering of hosts files.
+//!
+//! Lines hostie doesn't understand are kept verbatim, and entries that are not
+//! modified are written back exactly as they were read, so rewriting the file
+//! never disturbs its formatting.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use crate::file;
+use crate::owner::Owner;
+
+/// Prefix marking hostie's own metadata inside an entry's inline comment,
+/// e.g. `10.1.2.3 pay.internal # owner: payments hostie:tags=payments`.
+const META_PREFIX: &str = "hostie:";
+
+/// Markers around a named block of lines that hostie manages as a whole,
+/// e.g. `# BEGIN hostie blocklist:ads` ... `# END hostie blocklist:ads`.
+pub const BLOCK_BEGIN: &str = "# BEGIN hostie";
+pub const BLOCK_END: &str = "# END hostie";
+
+static BLOCK_MARKERS: OnceLock<(String, String)> = OnceLock::new();
+
+/// Uses other markers than `BLOCK_BEGIN` and `BLOCK_END` for managed blocks
+/// from now on. Only the first call has an effect.
+pub fn set_block_markers(begin: &str, end: &str) {
+    let _ = BLOCK_MARKERS.set((begin.trim().to_string(), end.trim().to_string()));
+}
+
+fn block_markers() -> (&'static str, &'static str) {
+    match BLOCK_MARKERS.get() {
+        Some((begin, end)) => (begin, end),
+        None => (BLOCK_BEGIN, BLOCK_END),
+    }
+}
+
+/// The block name in a marker line, e.g. `ads` in `# END hostie ads`.
+fn marker_name<'a>(text: &'a str, marker: &str) -> Option<&'a str> {
+    text.trim().strip_prefix(marker)?.strip_prefix(' ')
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HostsFile {
+    lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Line {
+    Entry(Entry),
+    /// Comments, blank lines and anything else that isn't an entry
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub ip: String,
+    pub hostnames: Vec<String>,
+    /// Free-form text of the inline comment, without hostie's metadata
+    pub comment: Option<String>,
+    pub tags: Vec<String>,
+    /// When the entry should be garbage collected
+    pub expires: Option<SystemTime>,
+    /// Local port that `hostie proxy` routes the hostnames to
+    pub port: Option<u16>,
+    /// The `hostie run` or `HostsGuard` process the entry lasts for
+    pub run: Option<Owner>,
+    /// The line this entry was parsed from
+    raw: Option<String>,
+}
+
+impl HostsFile {
+    pub fn parse(contents: &str) -> Self {
+        let lines = contents
+            .lines()
+            .map(|line| match Entry::parse(line) {
+                Some(entry) => Line::Entry(entry),
+                None => Line::Other(line.to_string()),
+            })
+            .collect();
+
+        HostsFile { lines }
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Writes the file in one step, see `file::replace`.
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        file::replace(path, &self.to_string())
+    }
+
+    /// Reads the file at `path`, lets `change` change it and writes it back,
+    /// all under `file::lock`, so that processes changing the file at the same
+    /// time, `hostie` commands and `HostsGuard`s alike, don't undo each
+    /// other's changes. Nothing is written if `change` fails or leaves the
+    /// file as it was.
+    pub fn update<T, E: From<io::Error>>(
+        path: impl AsRef<Path>,
+        change: impl FnOnce(&mut HostsFile) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let path = path.as_ref();
+        let _lock = file::lock(path)?;
+        let mut hosts = HostsFile::read(path)?;
+        let before = hosts.to_string();
+
+        let result = change(&mut hosts)?;
+        if hosts.to_string() != before {
+            hosts.write(path)?;
+        }
+        Ok(result)
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    pub fn lines_mut(&mut self) -> &mut Vec<Line> {
+        &mut self.lines
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Entry(entry) => Some(entry),
+            Line::Other(_) => None,
+        })
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        self.lines.push(Line::Entry(entry));
+    }
+
+    /// Lines along with the name of the managed block they are in, if any.
+    /// The markers themselves are outside of the block.
+    pub fn lines_by_block(&self) -> impl Iterator<Item = (Option<&str>, &Line)> {
+        let (begin, end) = block_markers();
+        let mut block = None;
+        self.lines.iter().map(move |line| {
+            if let Line::Other(text) = line {
+                if let Some(name) = marker_name(text, begin) {
+                    block = Some(name);
+                    return (None, line);
+                } else if block.is_some() && marker_name(text, end) == block {
+                    block = None;
+                    return (None, line);
+                }
+            }
+            (block, line)
+        })
+    }
+
+    /// Entries along with the name of the managed block they are in, if any.
+    pub fn entries_by_block(&self) -> impl Iterator<Item = (Option<&str>, &Entry)> {
+        self.lines_by_block()
+            .filter_map(|(block, line)| match line {
+                Line::Entry(entry) => Some((block, entry)),
+                Line::Other(_) => None,
+            })
+    }
+
+    /// Names of all managed blocks, in file order.
+    pub fn block_names(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                Line::Other(text) => marker_name(text, block_markers().0),
+                Line::Entry(_) => None,
+            })
+            .filter(|name| self.block_range(name).is_some())
+            .collect()
+    }
+
+    /// The lines between the markers of the named block.
+    pub fn block(&self, name: &str) -> Option<&[Line]> {
+        let (begin, end) = self.block_range(name)?;
+        Some(&self.lines[begin + 1..end])
+    }
+
+    /// Replaces the contents of the named block, adding it to the end of the
+    /// file if it doesn't exist yet.
+    pub fn set_block(&mut self, name: &str, lines: Vec<Line>) {
+        match self.block_range(name) {
+            Some((begin, end)) => {
+                self.lines.splice(begin + 1..end, lines);
+            }
+            None => {
+                let (begin, end) = block_markers();
+                self.lines.push(Line::Other(format!("{begin} {name}")));
+                self.lines.extend(lines);
+                self.lines.push(Line::Other(format!("{end} {name}")));
+            }
+        }
+    }
+
+    /// Replaces the named block with `entries`, leaving out hostnames that
+    /// already have an entry outside of it. Returns the entries written.
+    pub fn sync_block(&mut self, name: &str, entries: Vec<Entry>) -> Vec<Entry> {
+        let mut taken: HashSet<String> = self
+            .entries_by_block()
+            .filter(|(block, _)| *block != Some(name))
+            .flat_map(|(_, entry)| entry.hostnames.iter().cloned())
+            .collect();
+
+        let entries: Vec<Entry> = entries
+            .into_iter()
+            .filter_map(|mut entry| {
+                entry
+                    .hostnames
+                    .retain(|hostname| taken.insert(hostname.clone()));
+                (!entry.hostnames.is_empty()).then_some(entry)
+            })
+            .collect();
+
+        self.set_block(name, entries.iter().cloned().map(Line::Entry).collect());
+        entries
+    }
+
+    /// Removes the named block including its markers, returning its contents.
+    pub fn remove_block(&mut self, name: &str) -> Option<Vec<Line>> {
+        let (begin, end) = self.block_range(name)?;
+        let mut removed: Vec<_> = self.lines.drain(begin..=end).collect();
+        removed.pop();
+        removed.remove(0);
+        Some(removed)
+    }
+
+    fn block_range(&self, name: &str) -> Option<(usize, usize)> {
+        let is_marker = |line: &Line, marker: &str| matches!(line, Line::Other(text) if marker_name(text, marker) == Some(name));
+
+        let (begin_marker, end_marker) = block_markers();
+        let begin = self
+            .lines
+            .iter()
+            .position(|line| is_marker(line, begin_marker))?;
+        let end = self.lines[begin..]
+            .iter()
+            .position(|line| is_marker(line, end_marker))?;
+        Some((begin, begin + end))
+    }
+
+    /// Removes every entry for which `keep` returns false and returns them.
+    pub fn remove_entries(&mut self, mut keep: impl FnMut(&Entry) -> bool) -> Vec<Entry> {
+        let mut removed = Vec::new();
+        self.lines.retain(|line| match line {
+            Line::Entry(entry) if !keep(entry) => {
+                removed.push(entry.clone());
+                false
+            }
+            _ => true,
+        });
+        removed
+    }
+}
+
+impl fmt::Display for HostsFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Single newline at the end, and nothing at all for an empty file
+        for line in &self.lines {
+            match line {
+                Line::Entry(entry) => writeln!(f, "{entry}")?,
+                Line::Other(text) => writeln!(f, "{text}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Line {
+    /// The entry on a line that comments it out, e.g. `# 127.0.0.1 app.local`,
+    /// as opposed to a comment that happens to have two words.
+    pub fn disabled_entry(&self) -> Option<Entry> {
+        let Line::Other(text) = self else {
+            return None;
+        };
+        let entry = Entry::parse(text.trim_start().strip_prefix('#')?.trim_start())?;
+        let is_entry = entry.ip.parse::<IpAddr>().is_ok()
+            && entry.hostnames.iter().all(|hostname| is_hostname(hostname));
+        is_entry.then_some(entry)
+    }
+}
+
+impl Entry {
+    pub fn new(ip: &str, hostname: &str) -> Self {
+        Entry {
+            ip: ip.to_string(),
+            hostnames: vec![hostname.to_string()],
+            comment: None,
+            tags: Vec::new(),
+            expires: None,
+            port: None,
+            run: None,
+            raw: None,
+        }
+    }
+
+    /// Parses a single line, returning `None` for comments, blank lines and
+    /// lines without at least an address and one hostname.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (data, comment) = match line.split_once('#') {
+            Some((data, comment)) => (data, Some(comment)),
+            None => (line, None),
+        };
+
+        let mut fields = data.split_whitespace();
+        let ip = fields.next()?.to_string();
+        let hostnames: Vec<String> = fields.map(str::to_string).collect();
+        if hostnames.is_empty() {
+            return None;
+        }
+
+        let mut entry = Entry {
+            ip,
+            hostnames,
+            comment: None,
+            tags: Vec::new(),
+            expires: None,
+            port: None,
+            run: None,
+            raw: Some(line.to_string()),
+        };
+
+        let mut text = Vec::new();
+        for word in comment.unwrap_or_default().split_whitespace() {
+            let meta = word
+                .strip_prefix(META_PREFIX)
+                .and_then(|meta| meta.split_once('='));
+            match meta {
+                Some(("tags", value)) => {
+                    entry
+                        .tags
+                        .extend(value.split(',').filter(|t| !t.is_empty()).map(String::from));
+                }
+                Some(("expires", value)) if entry.expires.is_none() => {
+                    match humantime::parse_rfc3339(value) {
+                        Ok(expires) => entry.expires = Some(expires),
+                        Err(_) => text.push(word),
+                    }
+                }
+                Some(("port", value)) if entry.port.is_none() => match value.parse() {
+                    Ok(port) => entry.port = Some(port),
+                    Err(_) => text.push(word),
+                },
+                Some(("run", value)) if entry.run.is_none() => match value.parse() {
+                    Ok(owner) => entry.run = Some(owner),
+                    Err(_) => text.push(word),
+                },
+                _ => text.push(word),
+            }
+        }
+        if !text.is_empty() {
+            entry.comment = Some(text.join(" "));
+        }
+
+        Some(entry)
+    }
+
+    pub fn has_hostname(&self, hostname: &str) -> bool {
+        self.hostnames.iter().any(|h| h == hostname)
+    }
+
+    /// Whether the address is IPv6. A hostname may have one entry per address
+    /// family, e.g. `127.0.0.1` and `::1`.
+    pub fn is_ipv6(&self) -> bool {
+        self.ip.contains(':')
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// The entry commented out, so it stays in the file without effect.
+    pub fn disable(&self) -> Line {
+        Line::Other(format!("# {self}"))
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Untouched entries are written back exactly as they were read
+        if let Some(raw) = &self.raw {
+            if Entry::parse(raw).as_ref() == Some(self) {
+                return f.write_str(raw);
+            }
+        }
+
+        write!(f, "{} {}", self.ip, self.hostnames.join(" "))?;
+
+        let mut annotations = Vec::new();
+        if let Some(comment) = &self.comment {
+            annotations.push(comment.clone());
+        }
+        if !self.tags.is_empty() {
+            annotations.push(format!("{META_PREFIX}tags={}", self.tags.join(",")));
+        }
+        if let Some(expires) = self.expires {
+            annotations.push(format!(
+                "{META_PREFIX}expires={}",
+                humantime::format_rfc3339_seconds(expires)
+            ));
+        }
+        if let Some(port) = self.port {
+            annotations.push(format!("{META_PREFIX}port={port}"));
+        }
+        if let Some(owner) = self.run {
+            annotations.push(format!("{META_PREFIX}run={owner}"));
+        }
+        if !annotations.is_empty() {
+            write!(f, " # {}", annotations.join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `name` can go in a hosts file, which rules out the wildcards,
+/// regular expressions and the like that proxies and web servers accept.
+pub fn is_hostname(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_')
+}
+
+/// Whether `tag` can be written to and read back from a `hostie:tags=` list.
+pub fn is_tag(tag: &str) -> bool {
+    !tag.is_empty() && !tag.contains(|c: char| c.is_whitespace() || c == ',' || c == '#')
+}
+
+/// Whether `comment` is read back as the same comment, rather than partly as
+/// hostie's annotations or as another line.
+pub fn is_comment(comment: &str) -> bool {
+    !comment.contains(['\n', '\r'])
+        && !comment
+            .split_whitespace()
+            .any(|word| word.starts_with(META_PREFIX))
+}
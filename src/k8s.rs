@@ -0,0 +1,201 @@
+//! Entries for the hostnames routed by Kubernetes manifests.
+//!
+//! Hostnames come from `Ingress.spec.rules[].host`, `HTTPRoute.spec.hostnames`
+//! and `Gateway.spec.listeners[].hostname`, and all point at the address of
+//! the cluster's ingress, e.g. the kind or minikube node.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use colored::{ColoredString, Colorize};
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use crate::hosts::Entry;
+use crate::{Error, update_hosts};
+
+#[derive(Parser)]
+pub enum K8sCommand {
+    /// Add entries for the hostnames in a manifest or directory of manifests
+    Import {
+        /// A YAML manifest, or a directory to search for them
+        #[arg(value_name = "PATH")]
+        path: PathBuf,
+
+        /// The IP address of the ingress, e.g. from `minikube ip`
+        #[arg(long)]
+        ip: String,
+
+        /// Name for this set of entries, defaults to the file or directory name
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Remove entries added by `k8s import`
+    Remove {
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+}
+
+pub fn run(cmd: K8sCommand) -> Result<ColoredString, Error> {
+    match cmd {
+        K8sCommand::Import { path, ip, name } => import_manifests(&path, &ip, name),
+        K8sCommand::Remove { name } => remove_manifests(&name),
+    }
+}
+
+fn import_manifests(path: &Path, ip: &str, name: Option<String>) -> Result<ColoredString, Error> {
+    let name = match name {
+        Some(name) => name,
+        None => fs::canonicalize(path)?
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "default".to_string()),
+    };
+
+    let mut hostnames: Vec<String> = Vec::new();
+    for hostname in manifest_hostnames(path)? {
+        if hostname.contains('*') {
+            eprintln!(
+                "{}",
+                format!("Skipping wildcard hostname {hostname}, hosts files can't express it")
+                    .yellow()
+            );
+        } else if !hostnames.contains(&hostname) {
+            hostnames.push(hostname);
+        }
+    }
+
+    if hostnames.is_empty() {
+        return Err(Error::Generic(
+            format!("No hostnames found in {}", path.display()).red(),
+        ));
+    }
+
+    let entries: Vec<Entry> = hostnames
+        .iter()
+        .map(|hostname| Entry::new(ip, hostname))
+        .collect();
+    let written = update_hosts(|hosts| Ok(hosts.sync_block(&block_name(&name), entries)))?;
+
+    let mut lines = vec![format!(
+        "Imported {} entries as {}:",
+        written.len(),
+        name.yellow().bold()
+    )];
+    lines.extend(written.iter().map(|entry| {
+        format!(
+            "{} {}",
+            entry.ip.cyan().bold(),
+            entry.hostnames.join(" ").magenta().bold()
+        )
+    }));
+
+    Ok(lines.join("\n").green())
+}
+
+fn remove_manifests(name: &str) -> Result<ColoredString, Error> {
+    update_hosts(|hosts| match hosts.remove_block(&block_name(name)) {
+        Some(_) => Ok(()),
+        None => Err(Error::Generic(
+            format!("No imported entries named {}", name.yellow().bold()).red(),
+        )),
+    })?;
+
+    Ok(format!("Removed imported entries {}", name.yellow().bold()).green())
+}
+
+/// Hostnames from the manifest at `path`, or from every YAML file below it
+/// if it's a directory. Files in a directory that aren't valid YAML, such as
+/// Helm templates, are skipped.
+fn manifest_hostnames(path: &Path) -> Result<Vec<String>, Error> {
+    if !path.is_dir() {
+        return file_hostnames(path);
+    }
+
+    let mut files = Vec::new();
+    collect_yaml_files(path, &mut files, &mut HashSet::new())?;
+    files.sort();
+
+    let mut hostnames = Vec::new();
+    for file in files {
+        match file_hostnames(&file) {
+            Ok(found) => hostnames.extend(found),
+            Err(err) => eprintln!("{}", format!("Skipping {}: {err}", file.display()).yellow()),
+        }
+    }
+
+    Ok(hostnames)
+}
+
+/// The YAML files in and below `dir`. Directories are only searched once, so
+/// that symlinks like `up -> ..` don't loop forever.
+fn collect_yaml_files(
+    dir: &Path,
+    files: &mut Vec<PathBuf>,
+    visited: &mut HashSet<PathBuf>,
+) -> Result<(), Error> {
+    if !visited.insert(fs::canonicalize(dir)?) {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_yaml_files(&path, files, visited)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == "yaml" || ext == "yml")
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn file_hostnames(path: &Path) -> Result<Vec<String>, Error> {
+    let contents = fs::read_to_string(path)?;
+
+    let mut hostnames = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(&contents) {
+        hostnames.extend(resource_hostnames(&Value::deserialize(document)?));
+    }
+
+    Ok(hostnames)
+}
+
+fn resource_hostnames(resource: &Value) -> Vec<String> {
+    let strings = |values: &Value, key: &str| -> Vec<String> {
+        values
+            .as_sequence()
+            .into_iter()
+            .flatten()
+            .filter_map(|value| match key {
+                "" => value.as_str(),
+                key => value[key].as_str(),
+            })
+            .map(String::from)
+            .collect()
+    };
+
+    let spec = &resource["spec"];
+    match resource["kind"].as_str() {
+        // `kubectl get -o yaml` wraps resources in a list
+        Some("List") => resource["items"]
+            .as_sequence()
+            .into_iter()
+            .flatten()
+            .flat_map(resource_hostnames)
+            .collect(),
+        Some("Ingress") => strings(&spec["rules"], "host"),
+        Some("HTTPRoute" | "GRPCRoute" | "TLSRoute") => strings(&spec["hostnames"], ""),
+        Some("Gateway") => strings(&spec["listeners"], "hostname"),
+        _ => Vec::new(),
+    }
+}
+
+fn block_name(name: &str) -> String {
+    format!("k8s:{name}")
+}
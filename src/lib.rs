@@ -0,0 +1,9 @@
+//! The parts of hostie that other programs can use: the hosts file parser,
+//! the locations of hostie's files, safe writes and temporary entries for
+//! tests.
+
+pub mod file;
+pub mod guard;
+pub mod hosts;
+pub mod owner;
+pub mod paths;
@@ -1,18 +1,174 @@
-use std::fs::{self, OpenOptions, read_to_string};
-use std::io::prelude::*;
+mod aliases;
+mod blocklist;
+mod cert;
+mod completions;
+mod compose;
+mod config;
+mod containers;
+mod dns;
+mod edit;
+mod enforce;
+mod k8s;
+mod proxy;
+mod run;
+mod sandbox;
+mod tui;
+mod watch;
+mod webconfig;
+
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
 
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use clap_complete::{ArgValueCandidates, CompleteEnv};
 use colored::{ColoredString, Colorize};
 use thiserror::Error;
 
+use hostie::hosts::{self, Entry, HostsFile};
+use hostie::{owner, paths};
+
+use crate::config::Config;
+
+/// How many copies of the hosts file to keep in the backup directory.
+const KEPT_BACKUPS: usize = 10;
+
 fn main() -> ExitCode {
+    // Loaded first, as parsing `run --host` already needs the default IP
+    match Config::load() {
+        Ok(config) => {
+            if let Some(color) = config.color {
+                colored::control::set_override(color);
+            }
+            hosts::set_block_markers(&config.block_begin, &config.block_end);
+            let _ = CONFIG.set(config);
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    // Answers the shell when called from a completion script, with the
+    // entries of the hosts file the command being completed works on
+    if let Some(line) = completions::read_command_line() {
+        use_completed_options(&line);
+    }
+    CompleteEnv::with_factory(Options::command)
+        .var(completions::COMPLETE_VAR)
+        .complete();
+
     let opts: Options = Options::parse();
 
+    // With the IP optional, `add 10.0.0.1` would otherwise add a hostname
+    // that is really an address
+    if let SubCommand::Add(add) = &opts.subcmd {
+        if add.ip.is_none() && add.hostname.parse::<IpAddr>().is_ok() {
+            Options::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "the following required arguments were not provided:\n  <HOSTNAME>",
+                )
+                .exit();
+        }
+    }
+
+    // HOSTALIASES maps names to names, so the hosts file commands have no
+    // per-user equivalent
+    let is_user_command = matches!(
+        opts.subcmd,
+        SubCommand::AddAlias { .. } | SubCommand::RemoveAlias { .. } | SubCommand::Env
+    );
+    if opts.user && !is_user_command && !matches!(opts.subcmd, SubCommand::List(_)) {
+        Options::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--user only works with add-alias, remove-alias, list and env",
+            )
+            .exit();
+    }
+
+    let is_multi_file_command = matches!(
+        opts.subcmd,
+        SubCommand::Add(_) | SubCommand::Remove(_) | SubCommand::Path
+    );
+    if opts.hosts_files.len() > 1 && !is_multi_file_command {
+        Options::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "more than one --hosts-file only works with add, remove and path",
+            )
+            .exit();
+    }
+
+    if opts.store {
+        if let Err(err) = use_store() {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    }
+    if let [path] = opts.hosts_files.as_slice() {
+        set_hosts_path(path, "set by --hosts-file");
+    }
+    if let Some(root) = &opts.root {
+        if let Err(err) = use_root(root) {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(name) = &opts.netns {
+        let create = !matches!(opts.subcmd, SubCommand::Path);
+        if let Err(err) = use_netns(name, opts.root.as_deref(), create) {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if opts.hosts_files.len() > 1 && !matches!(opts.subcmd, SubCommand::Path) {
+        return run_on_each_file(&opts);
+    }
+
+    // Expired entries are cleaned up whenever hostie runs. This is best
+    // effort: without write access, e.g. `list` without sudo, they stay put.
+    if !opts.user
+        && !is_user_command
+        && !matches!(
+            opts.subcmd,
+            SubCommand::Gc | SubCommand::Path | SubCommand::Completions(_)
+        )
+    {
+        let _ = prune_expired_entries();
+    }
+
     let result = match opts.subcmd {
         SubCommand::Add(add) => add_hosts_entry(&add),
-        SubCommand::Remove(remove) => remove_hosts_entry(remove),
-        SubCommand::List => print_current_entries(),
+        SubCommand::Remove(remove) => remove_hosts_entry(&remove),
+        SubCommand::List(_) if opts.user => aliases::list_aliases(),
+        SubCommand::List(list) => print_current_entries(&list),
+        SubCommand::Gc => collect_expired_entries(),
+        SubCommand::Blocklist { cmd } => blocklist::run(cmd),
+        SubCommand::Watch => watch::watch_hosts_file(),
+        SubCommand::Enforce(enforce) => enforce::enforce_entries(&enforce),
+        SubCommand::Compose { cmd } => compose::run(cmd),
+        SubCommand::Containers { cmd } => containers::run(cmd),
+        SubCommand::K8s { cmd } => k8s::run(cmd),
+        SubCommand::ScanWebConfig(scan) => webconfig::scan_web_config(&scan),
+        SubCommand::ServeDns(serve) => dns::serve_dns(&serve),
+        SubCommand::Proxy(proxy) => proxy::run_proxy(&proxy),
+        SubCommand::Cert(cert) => cert::issue_certificate(&cert),
+        SubCommand::AddAlias { alias, hostname } => aliases::add_alias(&alias, &hostname),
+        SubCommand::RemoveAlias { alias } => aliases::remove_alias(&alias),
+        SubCommand::Env => aliases::print_env(),
+        SubCommand::Sandbox(sandbox) => sandbox::run_sandboxed(&sandbox),
+        SubCommand::Run(run) => run::run_command(&run),
+        SubCommand::Path => print_hosts_path(&opts.hosts_files),
+        SubCommand::Completions(shell) => completions::print_completions(&shell),
+        SubCommand::Tui => tui::run_tui(),
+        SubCommand::Edit => edit::edit_hosts_file(),
     };
 
     match result {
@@ -27,121 +183,393 @@ fn main() -> ExitCode {
     }
 }
 
-fn add_hosts_entry(add: &AddRemove) -> Result<ColoredString, Error> {
-    let new_entry = format!("{} {}", add.ip.cyan().bold(), add.hostname.magenta().bold());
-    let new_entry_line = format!("{} {}", add.ip, add.hostname);
+/// Runs `add` or `remove` on every `--hosts-file` in turn, carrying on past
+/// files where it fails.
+fn run_on_each_file(opts: &Options) -> ExitCode {
+    let mut code = ExitCode::SUCCESS;
+    for path in &opts.hosts_files {
+        set_hosts_path(path, "set by --hosts-file");
+        let _ = prune_expired_entries();
 
-    let contents = read_to_string(get_hosts_path())?;
+        let result = match &opts.subcmd {
+            SubCommand::Add(add) => add_hosts_entry(add),
+            SubCommand::Remove(remove) => remove_hosts_entry(remove),
+            _ => unreachable!("only add and remove work on several files"),
+        };
+        match result {
+            Ok(msg) => println!("{}: {msg}", path.bold()),
+            Err(err) => {
+                eprintln!("{}: {err}", path.bold());
+                code = ExitCode::FAILURE;
+            }
+        }
+    }
+    code
+}
 
-    // Check for exact hostname match (not just ends_with)
-    let hostname_exists = contents
-        .lines()
-        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
-        .any(|line| {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            parts.get(1).is_some_and(|h| h == &add.hostname)
-        });
+fn add_hosts_entry(add: &Add) -> Result<ColoredString, Error> {
+    let ips = if add.dual_stack {
+        vec!["127.0.0.1", "::1"]
+    } else {
+        vec![add.ip.as_deref().unwrap_or(&config().default_ip)]
+    };
 
-    if hostname_exists {
-        return Err(Error::Generic(
-            format!("Entry already exists: {new_entry}").red(),
+    let (added, unblocked, issued) = update_hosts(|hosts| {
+        let mut added = Vec::new();
+        for ip in &ips {
+            let new_entry = format!("{} {}", ip.cyan().bold(), add.hostname.magenta().bold());
+            let mut entry = Entry::new(ip, &add.hostname);
+
+            // Check for exact hostname match (not just ends_with). An IPv4
+            // and an IPv6 address for the same hostname don't clash, and
+            // blocklists give way to hand-written entries.
+            if hosts.entries_by_block().any(|(block, e)| {
+                !blocklist::is_blocklist_block(block)
+                    && e.has_hostname(&add.hostname)
+                    && e.is_ipv6() == entry.is_ipv6()
+            }) {
+                return Err(Error::Generic(
+                    format!("Entry already exists: {new_entry}").red(),
+                ));
+            }
+
+            entry.comment = add.comment.clone();
+            entry.tags = add.tags.clone();
+            if let Some(ttl) = add.ttl {
+                entry.expires = Some(SystemTime::now() + ttl);
+            }
+            entry.port = add.port;
+            hosts.push(entry);
+            added.push(new_entry);
+        }
+        let unblocked = blocklist::unblock(hosts, &add.hostname);
+        // Before the entry is written, so that it isn't left behind on failure
+        let issued = add.tls.then(|| cert::issue(&add.hostname)).transpose()?;
+        Ok((added, unblocked, issued))
+    })?;
+
+    let mut message = if added.len() == 1 {
+        format!("Added entry to hosts file: {}", added[0])
+    } else {
+        format!("Added entries to hosts file: {}", added.join(", "))
+    };
+    if unblocked {
+        message.push_str(" (removed from blocklists)");
+    }
+    if let Some(port) = add.port {
+        message.push_str(&format!(" (proxied to port {port})"));
+    }
+    if let Some(ttl) = add.ttl {
+        message.push_str(&format!(
+            " (expires in {})",
+            humantime::format_duration(ttl)
         ));
     }
+    if let Some(issued) = issued {
+        message.push('\n');
+        message.push_str(&issued.describe());
+    }
+    Ok(message.green())
+}
 
-    let mut file = OpenOptions::new().append(true).open(get_hosts_path())?;
-    file.write_all(format!("{}\n", new_entry_line).as_bytes())?;
+fn remove_hosts_entry(remove: &Remove) -> Result<ColoredString, Error> {
+    let protected_hostnames = &config().protected;
 
-    Ok(format!("Added entry to hosts file: {new_entry}").green())
-}
+    if let Some(tag) = &remove.tag {
+        return remove_tagged_entries(tag, protected_hostnames);
+    }
 
-fn remove_hosts_entry(remove: AddRemove) -> Result<ColoredString, Error> {
-    let protected_hostnames = ["localhost", "broadcasthost"];
+    // clap guarantees both are present when no tag is given
+    let (Some(ip), Some(hostname)) = (&remove.ip, &remove.hostname) else {
+        unreachable!("IP and HOSTNAME are required without --tag");
+    };
 
-    if protected_hostnames.contains(&remove.hostname.as_str()) {
+    if protected_hostnames.contains(hostname) {
         return Err(Error::Generic(
             format!(
                 "Cannot remove protected entry: {}",
-                remove.hostname.magenta().bold()
+                hostname.magenta().bold()
             )
             .red(),
         ));
     }
 
-    let contents = read_to_string(get_hosts_path())?;
+    let entry_to_remove = format!("{} {}", ip.cyan().bold(), hostname.magenta().bold());
 
-    let entry_to_remove = format!(
-        "{} {}",
-        remove.ip.cyan().bold(),
-        remove.hostname.magenta().bold()
-    );
-    let entry_to_remove_line = format!("{} {}", remove.ip, remove.hostname);
-
-    // Check for exact IP+hostname match
-    let entry_exists = contents
-        .lines()
-        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
-        .any(|line| {
-            let normalized = line.split_whitespace().collect::<Vec<_>>().join(" ");
-            normalized == entry_to_remove_line
+    update_hosts(|hosts| {
+        // Remove only entries with the exact IP+hostname, whatever their comment
+        let removed =
+            hosts.remove_entries(|entry| entry.ip != *ip || entry.hostnames != [hostname.as_str()]);
+
+        if removed.is_empty() {
+            return Err(Error::Generic(
+                format!("Entry does not exist: {entry_to_remove}").red(),
+            ));
+        }
+        Ok(())
+    })?;
+
+    Ok(format!("Removed entry from hosts file: {entry_to_remove}").green())
+}
+
+fn remove_tagged_entries(
+    tag: &str,
+    protected_hostnames: &[String],
+) -> Result<ColoredString, Error> {
+    let removed = update_hosts(|hosts| {
+        let removed = hosts.remove_entries(|entry| {
+            !entry.has_tag(tag)
+                || entry
+                    .hostnames
+                    .iter()
+                    .any(|h| protected_hostnames.contains(h))
         });
 
-    if !entry_exists {
-        return Err(Error::Generic(
-            format!("Entry does not exist: {entry_to_remove}").red(),
-        ));
-    }
+        if removed.is_empty() {
+            return Err(Error::Generic(
+                format!("No entries tagged {}", tag.yellow().bold()).red(),
+            ));
+        }
+        Ok(removed)
+    })?;
 
-    // Remove only the exact matching line
-    let entries: Vec<_> = contents
-        .lines()
-        .filter(|line| {
-            if line.trim().is_empty() || line.starts_with('#') {
-                true // Keep comments and empty lines
-            } else {
-                let normalized = line.split_whitespace().collect::<Vec<_>>().join(" ");
-                normalized != entry_to_remove_line
-            }
+    let removed = removed
+        .iter()
+        .map(|entry| {
+            format!(
+                "Removed entry from hosts file: {} {}",
+                entry.ip.cyan().bold(),
+                entry.hostnames.join(" ").magenta().bold()
+            )
         })
-        .collect();
+        .collect::<Vec<_>>()
+        .join("\n");
 
-    // Write back with proper formatting (single newline at end)
-    let new_content = if entries.is_empty() {
-        String::new()
-    } else {
-        format!("{}\n", entries.join("\n"))
-    };
+    Ok(removed.green())
+}
 
-    fs::write(get_hosts_path(), new_content)?;
+fn print_current_entries(list: &List) -> Result<ColoredString, Error> {
+    let hosts = HostsFile::read(get_hosts_path())?;
 
-    Ok(format!(
-        "Removed entry from hosts file: {} {}",
-        remove.ip.cyan().bold(),
-        remove.hostname.magenta().bold()
-    )
-    .green())
+    // Imported blocklists run to 100k+ entries; `blocklist list` sums them up
+    let current_entries = hosts
+        .entries_by_block()
+        .filter(|(block, _)| !blocklist::is_blocklist_block(*block))
+        .map(|(_, entry)| entry)
+        .filter(|entry| list.tag.as_ref().is_none_or(|tag| entry.has_tag(tag)))
+        .map(|entry| {
+            let mut line = format!(
+                "{} {}",
+                entry.ip.cyan().bold(),
+                entry.hostnames.join(" ").magenta().bold()
+            );
+            if let Some(comment) = &entry.comment {
+                line.push_str(&format!(" # {comment}"));
+            }
+            if !entry.tags.is_empty() {
+                line.push_str(&format!(" [{}]", entry.tags.join(", ").yellow()));
+            }
+            if let Some(port) = entry.port {
+                line.push_str(&format!(" (port {port})"));
+            }
+            if let Some(expires) = entry.expires {
+                line.push_str(&format!(
+                    " (expires {})",
+                    humantime::format_rfc3339_seconds(expires)
+                ));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok(current_entries.green())
 }
 
-fn print_current_entries() -> Result<ColoredString, Error> {
-    let contents = read_to_string(get_hosts_path())?;
+fn collect_expired_entries() -> Result<ColoredString, Error> {
+    let removed = prune_expired_entries()?;
+
+    if removed.is_empty() {
+        return Ok("No expired entries in hosts file".green());
+    }
 
-    let current_entries = contents
-        .lines()
-        .filter(|line| !line.starts_with('#') && !line.is_empty())
-        .map(|e| {
-            let parts: Vec<&str> = e.split_whitespace().collect();
-            let ip = parts.first().unwrap_or(&"");
-            let hostname = parts.get(1).unwrap_or(&"");
-            format!("{} {}", ip.cyan().bold(), hostname.magenta().bold())
+    let removed = removed
+        .iter()
+        .map(|entry| {
+            let reason = if entry.run.is_some() {
+                "Removed leftover entry of an ended `hostie run`"
+            } else {
+                "Removed expired entry"
+            };
+            format!(
+                "{reason}: {} {}",
+                entry.ip.cyan().bold(),
+                entry.hostnames.join(" ").magenta().bold()
+            )
         })
         .collect::<Vec<_>>()
         .join("\n");
 
-    Ok(current_entries.green())
+    Ok(removed.green())
+}
+
+/// Removes entries whose `--ttl` has run out or whose `hostie run` is gone,
+/// only touching the file when there is something to remove.
+fn prune_expired_entries() -> Result<Vec<Entry>, Error> {
+    // Most invocations find nothing to prune, and shouldn't wait for the lock
+    let now = SystemTime::now();
+    let is_stale = |entry: &Entry| entry.is_expired(now) || run::is_leftover(entry);
+    if !HostsFile::read(get_hosts_path())?.entries().any(is_stale) {
+        return Ok(Vec::new());
+    }
+
+    update_hosts(|hosts| Ok(hosts.remove_entries(|entry| !is_stale(entry))))
+}
+
+/// Points every command at the per-user store, creating it on first use.
+fn use_store() -> Result<(), Error> {
+    let path = paths::store_path();
+    if !path.exists() {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, "")?;
+    }
+
+    set_hosts_path(&path.to_string_lossy(), "hostie's store, chosen by --store");
+    Ok(())
+}
+
+/// Points the completion candidates at the hosts file the options on the
+/// command line being completed choose. Unlike running the command, this
+/// never creates a file, and errors only mean fewer candidates.
+fn use_completed_options(line: &completions::CommandLine) {
+    if line.store {
+        set_hosts_path(&paths::store_path().to_string_lossy(), "");
+    }
+    if let Some(path) = &line.hosts_file {
+        set_hosts_path(path, "");
+    }
+    if let Some(root) = &line.root {
+        let _ = use_root(root);
+    }
+    if let Some(name) = &line.netns {
+        let _ = use_netns(name, line.root.as_deref(), false);
+    }
+}
+
+/// Points every command at the hosts file of the system image in `root`.
+fn use_root(root: &Path) -> Result<(), Error> {
+    if !root.is_dir() {
+        return Err(Error::Generic(
+            format!("Not a directory: {}", root.display()).red(),
+        ));
+    }
+
+    let path = paths::resolve_in_root(root, Path::new("/etc/hosts"))?;
+    set_hosts_path(
+        &path.to_string_lossy(),
+        &format!("/etc/hosts inside --root {}", root.display()),
+    );
+    Ok(())
+}
+
+/// Points every command at the hosts file `ip netns exec` mounts over
+/// `/etc/hosts` for the named network namespace, creating it from the hosts
+/// file used otherwise if `create` is set.
+fn use_netns(name: &str, root: Option<&Path>, create: bool) -> Result<(), Error> {
+    // `ip netns` puts the name in paths like /run/netns/<name>
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(Error::Generic(
+            format!(
+                "Not a valid network namespace name: {}",
+                name.yellow().bold()
+            )
+            .red(),
+        ));
+    }
+
+    let netns_path = Path::new("/etc/netns").join(name).join("hosts");
+    let path = match root {
+        Some(root) => paths::resolve_in_root(root, &netns_path)?,
+        None => netns_path,
+    };
+
+    if create && !path.exists() {
+        let main = get_hosts_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::copy(&main, &path)?;
+        eprintln!(
+            "{}",
+            format!("Created {} from {main}", path.display()).green()
+        );
+    }
+
+    set_hosts_path(
+        &path.to_string_lossy(),
+        &format!("hosts file of network namespace {name}, chosen by --netns"),
+    );
+    Ok(())
+}
+
+/// Prints the file(s) commands work on, explaining on stderr how it was
+/// chosen, so `$(hostie path)` gives just the path.
+fn print_hosts_path(hosts_files: &[String]) -> Result<ColoredString, Error> {
+    if hosts_files.len() > 1 {
+        eprintln!("{}", "set by --hosts-file".dimmed());
+        return Ok(hosts_files.join("\n").normal());
+    }
+
+    let (path, reason) = resolve_hosts_path();
+    eprintln!("{}", reason.dimmed());
+    Ok(path.normal())
 }
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Options {
+    /// Work on your per-user aliases file (`HOSTALIASES`, default `~/.hosts`)
+    /// instead of the hosts file, which needs no root
+    #[arg(long, global = true)]
+    user: bool,
+
+    /// Work on hostie's own store, which the NSS module answers lookups from,
+    /// instead of the hosts file
+    #[arg(long, global = true, conflicts_with = "user")]
+    store: bool,
+
+    /// Work on this hosts file instead of the system's; `add` and `remove`
+    /// accept several to change them all (repeatable)
+    #[arg(
+        long = "hosts-file",
+        value_name = "PATH",
+        global = true,
+        conflicts_with_all = ["user", "store"]
+    )]
+    hosts_files: Vec<String>,
+
+    /// Work on `/etc/hosts` of the system image or chroot in this directory,
+    /// following symlinks as they would resolve inside it
+    #[arg(
+        long,
+        value_name = "DIR",
+        global = true,
+        conflicts_with_all = ["user", "store", "hosts_files"]
+    )]
+    root: Option<PathBuf>,
+
+    /// Work on the hosts file `ip netns exec` uses for this network namespace,
+    /// `/etc/netns/<NAME>/hosts`, creating it from the hosts file if needed
+    #[arg(
+        long,
+        value_name = "NAME",
+        global = true,
+        conflicts_with_all = ["user", "store", "hosts_files"]
+    )]
+    netns: Option<String>,
+
     #[command(subcommand)]
     subcmd: SubCommand,
 }
@@ -149,38 +577,302 @@ struct Options {
 #[derive(Parser)]
 enum SubCommand {
     /// Add a new entry to your hosts file
-    Add(AddRemove),
+    Add(Add),
     /// Remove an entry from your hosts file
-    Remove(AddRemove),
+    Remove(Remove),
     /// List all entries in your hosts file
-    List,
+    List(List),
+    /// Remove entries whose time to live has expired, and leftovers of
+    /// `hostie run` sessions that ended without cleaning up
+    Gc,
+    /// Manage imported blocklists and the domains they may not block
+    Blocklist {
+        #[command(subcommand)]
+        cmd: blocklist::BlocklistCommand,
+    },
+    /// Print changes made to your hosts file by other programs as they happen
+    Watch,
+    /// Keep the entries from a manifest in your hosts file, re-adding them
+    /// whenever another program removes or alters them
+    Enforce(enforce::Enforce),
+    /// Add or remove entries for the services in a docker-compose file
+    Compose {
+        #[command(subcommand)]
+        cmd: compose::ComposeCommand,
+    },
+    /// Map running Docker or Podman containers to their IP addresses
+    Containers {
+        #[command(subcommand)]
+        cmd: containers::ContainersCommand,
+    },
+    /// Add entries for the hostnames in Kubernetes Ingress and Gateway API
+    /// manifests
+    K8s {
+        #[command(subcommand)]
+        cmd: k8s::K8sCommand,
+    },
+    /// Find the server names in nginx, Apache or Caddy configs that have no
+    /// entry yet
+    ScanWebConfig(webconfig::ScanWebConfig),
+    /// Answer DNS queries from your hosts file, with wildcard support
+    ServeDns(dns::ServeDns),
+    /// Route HTTP requests to the local port recorded with `add --port`
+    Proxy(proxy::Proxy),
+    /// Issue a TLS certificate for a hostname and its aliases, signed by a
+    /// local CA
+    Cert(cert::Cert),
+    /// Make a single name resolve like another hostname, for your user only
+    AddAlias {
+        /// The new name, without dots
+        #[arg(value_name = "ALIAS")]
+        alias: String,
+
+        /// The hostname it stands for
+        #[arg(value_name = "HOSTNAME")]
+        hostname: String,
+    },
+    /// Remove an alias added with `add-alias`
+    RemoveAlias {
+        #[arg(value_name = "ALIAS")]
+        alias: String,
+    },
+    /// Print the shell line that points `HOSTALIASES` at your aliases file
+    Env,
+    /// Run a command that sees extra entries in /etc/hosts, without root and
+    /// without changing the real file (Linux only)
+    Sandbox(sandbox::Sandbox),
+    /// Add entries while a command runs, removing them when it exits
+    Run(run::Run),
+    /// Print the hosts file hostie works on and why it was chosen
+    Path,
+    /// Print the script that sets up completions for your shell
+    Completions(completions::Completions),
+    /// Browse, filter, enable, disable, edit and delete entries interactively
+    Tui,
+    /// Edit the hosts file in $VISUAL or $EDITOR, checking it before saving
+    Edit,
 }
 
 #[derive(Parser)]
-struct AddRemove {
-    /// The IP address to use
+#[command(allow_missing_positional = true)]
+struct Add {
+    /// The IP address to use, defaults to 127.0.0.1 or `default_ip` from the
+    /// config file
     #[arg(value_name = "IP")]
-    ip: String,
+    ip: Option<String>,
 
     /// The hostname to associate with the IP address
     #[arg(value_name = "HOSTNAME")]
     hostname: String,
+
+    /// A note to keep next to the entry, e.g. "owner: payments team"
+    #[arg(long, value_parser = parse_comment)]
+    comment: Option<String>,
+
+    /// Tag the entry so it can be listed or removed as a group (repeatable)
+    #[arg(long = "tag", value_name = "TAG", value_parser = parse_tag)]
+    tags: Vec<String>,
+
+    /// Remove the entry automatically after this long, e.g. `30m` or `2h`
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    ttl: Option<Duration>,
+
+    /// Local port that `hostie proxy` should route the hostname to
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// Also issue a TLS certificate for the hostname, like `hostie cert`
+    #[arg(long)]
+    tls: bool,
+
+    /// Map the hostname to both 127.0.0.1 and ::1, so it resolves locally
+    /// over IPv4 and IPv6
+    #[arg(long, conflicts_with = "ip")]
+    dual_stack: bool,
+}
+
+fn parse_comment(value: &str) -> Result<String, String> {
+    if !hosts::is_comment(value) {
+        return Err("comments can't span lines or contain words starting with `hostie:`".into());
+    }
+    Ok(value.to_string())
+}
+
+fn parse_tag(value: &str) -> Result<String, String> {
+    if !hosts::is_tag(value) {
+        return Err("tags can't be empty or contain spaces, `,` or `#`".into());
+    }
+    Ok(value.to_string())
+}
+
+#[derive(Parser)]
+struct Remove {
+    /// The IP address to use
+    #[arg(
+        value_name = "IP",
+        required_unless_present = "tag",
+        add = ArgValueCandidates::new(completions::ip_candidates)
+    )]
+    ip: Option<String>,
+
+    /// The hostname to associate with the IP address
+    #[arg(
+        value_name = "HOSTNAME",
+        required_unless_present = "tag",
+        add = ArgValueCandidates::new(completions::hostname_candidates)
+    )]
+    hostname: Option<String>,
+
+    /// Remove every entry with this tag instead
+    #[arg(
+        long,
+        conflicts_with_all = ["ip", "hostname"],
+        add = ArgValueCandidates::new(completions::tag_candidates)
+    )]
+    tag: Option<String>,
+}
+
+#[derive(Parser)]
+struct List {
+    /// Only list entries with this tag
+    #[arg(long, add = ArgValueCandidates::new(completions::tag_candidates))]
+    tag: Option<String>,
 }
 
 #[derive(Error, Debug)]
 enum Error {
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("http error: {0}")]
+    Http(#[from] ureq::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("yaml error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("watch error: {0}")]
+    Watch(#[from] notify::Error),
+    #[error("certificate error: {0}")]
+    Cert(#[from] rcgen::Error),
     #[error("{0}")]
     Generic(ColoredString),
 }
 
+/// Set from the command line when hostie should work on a file other than
+/// the configured or system hosts file, along with the reason `hostie path`
+/// gives for it.
+static HOSTS_PATH_OVERRIDE: Mutex<Option<(String, String)>> = Mutex::new(None);
+
+fn set_hosts_path(path: &str, reason: &str) {
+    let mut hosts_path = HOSTS_PATH_OVERRIDE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *hosts_path = Some((path.to_string(), reason.to_string()));
+}
+
 fn get_hosts_path() -> String {
-    std::env::var("HOSTIE_HOSTS_FILE").unwrap_or_else(|_| {
-        if cfg!(windows) {
-            r"C:\Windows\System32\drivers\etc\hosts".to_string()
-        } else {
-            "/etc/hosts".to_string()
+    resolve_hosts_path().0
+}
+
+/// The hosts file commands work on and why, in order of precedence.
+fn resolve_hosts_path() -> (String, String) {
+    let hosts_path = HOSTS_PATH_OVERRIDE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(resolved) = hosts_path.as_ref() {
+        return resolved.clone();
+    }
+
+    // The environment variable still wins, so tests never touch a configured
+    // hosts file
+    if let Some(path) = std::env::var_os("HOSTIE_HOSTS_FILE") {
+        return (
+            path.to_string_lossy().into_owned(),
+            "set by the HOSTIE_HOSTS_FILE environment variable".to_string(),
+        );
+    }
+
+    let config = config();
+    if let (Some(path), Some(source)) = (&config.hosts_file, &config.hosts_file_source) {
+        return (
+            path.to_string_lossy().into_owned(),
+            format!("set by hosts_file in {}", source.display()),
+        );
+    }
+
+    (
+        paths::system_hosts_path().to_string_lossy().into_owned(),
+        "the system hosts file".to_string(),
+    )
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// Changes the hosts file with `HostsFile::update`, first keeping a copy of
+/// it if a `backup_dir` is configured and `change` changes it.
+fn update_hosts<T>(change: impl FnOnce(&mut HostsFile) -> Result<T, Error>) -> Result<T, Error> {
+    HostsFile::update(get_hosts_path(), |hosts| {
+        let before = hosts.to_string();
+        let result = change(hosts)?;
+        if hosts.to_string() != before {
+            back_up_hosts_file()?;
         }
+        Ok(result)
     })
 }
+
+/// Keeps a copy of the hosts file as it is now if a `backup_dir` is
+/// configured.
+fn back_up_hosts_file() -> Result<(), Error> {
+    match &config().backup_dir {
+        Some(dir) => back_up(Path::new(&get_hosts_path()), dir),
+        None => Ok(()),
+    }
+}
+
+/// Copies the hosts file to e.g. `hosts.2025-06-01T12-00-00.123456Z` in
+/// `dir`, removing all but the most recent backups.
+fn back_up(path: &Path, dir: &Path) -> Result<(), Error> {
+    if !path.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir)?;
+
+    // Colons aren't allowed in file names on Windows
+    let time = humantime::format_rfc3339_micros(SystemTime::now())
+        .to_string()
+        .replace(':', "-");
+    fs::copy(path, dir.join(format!("hosts.{time}")))?;
+
+    let mut backups: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(is_backup_name)
+        })
+        .collect();
+    // The timestamps sort chronologically
+    backups.sort();
+    for old in backups.iter().rev().skip(KEPT_BACKUPS) {
+        fs::remove_file(old)?;
+    }
+
+    Ok(())
+}
+
+/// Whether `name` is that of a copy made by `back_up`, so that rotating them
+/// leaves other files in the directory, e.g. `hosts.allow`, alone.
+fn is_backup_name(name: &str) -> bool {
+    let Some((date, time)) = name
+        .strip_prefix("hosts.")
+        .and_then(|stamp| stamp.split_once('T'))
+    else {
+        return false;
+    };
+    humantime::parse_rfc3339(&format!("{date}T{}", time.replace('-', ":"))).is_ok()
+}
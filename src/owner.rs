@@ -0,0 +1,125 @@
+//! The processes that entries of `hostie run` and `HostsGuard` last for.
+//!
+//! A process ID alone doesn't tell whether the process is still around: after
+//! a reboot, or once IDs wrap around, the same ID belongs to another process.
+//! So the time the process started is recorded with it, e.g.
+//! `hostie:run=4242@81234`, and, as a container sharing the hosts file counts
+//! processes differently, the PID namespace, e.g.
+//! `hostie:run=4026531836/4242@81234`.
+
+use std::fmt;
+use std::fs;
+use std::process;
+use std::str::FromStr;
+
+/// A process an entry lasts for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Owner {
+    pub pid: u32,
+    /// When the process started, in clock ticks since boot
+    pub started: Option<u64>,
+    /// The inode of the PID namespace `pid` is counted in
+    pub namespace: Option<u64>,
+}
+
+impl Owner {
+    /// The current process.
+    pub fn current() -> Self {
+        let pid = process::id();
+        Owner {
+            pid,
+            started: start_time(pid),
+            namespace: pid_namespace(),
+        }
+    }
+
+    /// Whether the process is still running. Processes of other PID
+    /// namespaces can't be looked up, so they count as running.
+    pub fn is_running(&self) -> bool {
+        if self
+            .namespace
+            .zip(pid_namespace())
+            .is_some_and(|(theirs, ours)| theirs != ours)
+        {
+            return true;
+        }
+
+        // A process that started at another time merely got the same ID
+        exists(self.pid)
+            && self
+                .started
+                .zip(start_time(self.pid))
+                .is_none_or(|(recorded, actual)| recorded == actual)
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(namespace) = self.namespace {
+            write!(f, "{namespace}/")?;
+        }
+        write!(f, "{}", self.pid)?;
+        if let Some(started) = self.started {
+            write!(f, "@{started}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Owner {
+    type Err = std::num::ParseIntError;
+
+    /// Parses `[namespace/]pid[@started]`, e.g. `4242` as written by older
+    /// versions of hostie.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (namespace, value) = match value.split_once('/') {
+            Some((namespace, value)) => (Some(namespace.parse()?), value),
+            None => (None, value),
+        };
+        let (pid, started) = match value.split_once('@') {
+            Some((pid, started)) => (pid, Some(started.parse()?)),
+            None => (value, None),
+        };
+        Ok(Owner {
+            pid: pid.parse()?,
+            started,
+            namespace,
+        })
+    }
+}
+
+#[cfg(unix)]
+fn exists(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // EPERM means the process exists but belongs to someone else
+    pid > 0
+        && (unsafe { libc::kill(pid, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
+
+#[cfg(not(unix))]
+fn exists(_pid: u32) -> bool {
+    true
+}
+
+/// When the process started, from `/proc/<pid>/stat`, on Linux.
+fn start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name in parentheses may contain spaces and parentheses, so
+    // the fields are counted from after it, starting with the state (3rd)
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(22 - 3)?.parse().ok()
+}
+
+/// The PID namespace of the current process, on Linux, where
+/// `/proc/self/ns/pid` links to e.g. `pid:[4026531836]`.
+fn pid_namespace() -> Option<u64> {
+    let link = fs::read_link("/proc/self/ns/pid").ok()?;
+    link.to_str()?
+        .strip_prefix("pid:[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
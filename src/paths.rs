@@ -0,0 +1,201 @@
+//! Where hostie finds the hosts file and keeps files of its own.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// How many symlinks `resolve_in_root` follows before giving up, like Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// The hosts file: `$HOSTIE_HOSTS_FILE` if set, otherwise the system's.
+pub fn hosts_path() -> PathBuf {
+    if let Some(path) = env::var_os("HOSTIE_HOSTS_FILE") {
+        return PathBuf::from(path);
+    }
+
+    system_hosts_path()
+}
+
+pub fn system_hosts_path() -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from(r"C:\Windows\System32\drivers\etc\hosts")
+    } else {
+        PathBuf::from("/etc/hosts")
+    }
+}
+
+/// `path` inside `root` as a process chrooted to `root` would see it, e.g. an
+/// image's `/etc/hosts`: symlink targets starting with `/` are taken
+/// relative to `root`, and `..` never leads out of it. The last component
+/// doesn't have to exist.
+pub fn resolve_in_root(root: &Path, path: &Path) -> io::Result<PathBuf> {
+    let mut resolved = root.to_path_buf();
+    let mut depth = 0;
+    let mut pending = components(path);
+    let mut followed = 0;
+
+    while let Some(name) = pending.pop() {
+        if name == ".." {
+            if depth > 0 {
+                resolved.pop();
+                depth -= 1;
+            }
+            continue;
+        }
+
+        let candidate = resolved.join(&name);
+        let is_symlink = fs::symlink_metadata(&candidate).is_ok_and(|meta| meta.is_symlink());
+        if !is_symlink {
+            resolved = candidate;
+            depth += 1;
+            continue;
+        }
+
+        followed += 1;
+        if followed > MAX_SYMLINKS {
+            return Err(io::Error::other(format!(
+                "too many levels of symbolic links resolving {} in {}",
+                path.display(),
+                root.display()
+            )));
+        }
+        let target = fs::read_link(&candidate)?;
+        if target.has_root() {
+            resolved = root.to_path_buf();
+            depth = 0;
+        }
+        pending.extend(components(&target));
+    }
+
+    Ok(resolved)
+}
+
+/// The names and `..`s in `path`, last one first.
+fn components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .rev()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_os_string()),
+            Component::ParentDir => Some(OsString::from("..")),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => None,
+        })
+        .collect()
+}
+
+/// `$XDG_DATA_HOME/hostie`, falling back to `~/.local/share/hostie`, or
+/// `%LOCALAPPDATA%\hostie` on Windows.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir).join("hostie");
+    }
+
+    if cfg!(windows) {
+        if let Some(dir) = env::var_os("LOCALAPPDATA") {
+            return PathBuf::from(dir).join("hostie");
+        }
+    }
+
+    let home = env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    home.join(".local/share/hostie")
+}
+
+/// The user who ran `sudo hostie`, if that's how hostie was started.
+pub struct SudoUser {
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+}
+
+/// The user behind `sudo`, whose files hostie should use for things that
+/// aren't root's business, like the development CA their browser trusts.
+pub fn sudo_user() -> Option<SudoUser> {
+    #[cfg(unix)]
+    {
+        // Only root can have been started by sudo; for anyone else these
+        // variables are just inherited, or made up
+        if unsafe { libc::geteuid() } != 0 {
+            return None;
+        }
+        let uid: u32 = env::var("SUDO_UID").ok()?.parse().ok()?;
+        let gid: u32 = env::var("SUDO_GID").ok()?.parse().ok()?;
+        if uid == 0 {
+            return None;
+        }
+        Some(SudoUser {
+            uid,
+            gid,
+            home: home_of(uid)?,
+        })
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+#[cfg(unix)]
+fn home_of(uid: u32) -> Option<PathBuf> {
+    use std::ffi::CStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    let status =
+        unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if status != 0 || result.is_null() || passwd.pw_dir.is_null() {
+        return None;
+    }
+    let dir = unsafe { CStr::from_ptr(passwd.pw_dir) };
+    Some(PathBuf::from(std::ffi::OsStr::from_bytes(dir.to_bytes())))
+}
+
+/// Like `data_dir`, but the invoking user's under `sudo` instead of root's.
+pub fn sudo_user_data_dir() -> PathBuf {
+    match sudo_user() {
+        Some(user) if env::var_os("XDG_DATA_HOME").is_none_or(|dir| dir.is_empty()) => {
+            user.home.join(".local/share/hostie")
+        }
+        _ => data_dir(),
+    }
+}
+
+/// The per-user store read by the NSS module, a hosts-format file that
+/// `hostie --store` manages instead of the hosts file.
+pub fn store_path() -> PathBuf {
+    data_dir().join("hosts")
+}
+
+/// `$XDG_CONFIG_HOME/hostie`, falling back to `~/.config/hostie`, or
+/// `%APPDATA%\hostie` on Windows.
+pub fn config_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir).join("hostie");
+    }
+
+    if cfg!(windows) {
+        if let Some(dir) = env::var_os("APPDATA") {
+            return PathBuf::from(dir).join("hostie");
+        }
+    }
+
+    let home = env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    home.join(".config/hostie")
+}
+
+/// The config files in the order they are read, later ones overriding
+/// earlier ones: the system-wide file, then the user's. `$HOSTIE_CONFIG`
+/// replaces both with a single file.
+pub fn config_paths() -> Vec<PathBuf> {
+    if let Some(path) = env::var_os("HOSTIE_CONFIG") {
+        return vec![PathBuf::from(path)];
+    }
+
+    let system = if cfg!(windows) {
+        let dir = env::var_os("PROGRAMDATA").unwrap_or_else(|| r"C:\ProgramData".into());
+        PathBuf::from(dir).join(r"hostie\config.toml")
+    } else {
+        PathBuf::from("/etc/hostie/config.toml")
+    };
+    vec![system, config_dir().join("config.toml")]
+}
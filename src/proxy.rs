@@ -0,0 +1,431 @@
+//! A reverse proxy routing requests by their `Host` header.
+//!
+//! Entries added with `add --port` point a hostname at the loopback address
+//! and record a port, e.g. `127.0.0.1 myapp.test # hostie:port=3000`. The
+//! proxy listens on port 80 and forwards each request to the port recorded
+//! for its host, so `http://myapp.test` reaches the app on `localhost:3000`.
+//!
+//! On port 443 it does the same for `https://myapp.test`, with a certificate
+//! from hostie's local CA that is issued, like `hostie cert` does, the first
+//! time the hostname is asked for.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use clap::Parser;
+use colored::{ColoredString, Colorize};
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::hosts::HostsFile;
+use crate::{Error, cert, get_hosts_path};
+
+/// The most a request head may take up, request line and headers together.
+const MAX_HEAD: u64 = 64 * 1024;
+/// How long a tunnel waits on one side before checking the other.
+const TUNNEL_POLL: Duration = Duration::from_millis(10);
+
+#[derive(Parser)]
+pub struct Proxy {
+    /// Address to listen on
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:80")]
+    listen: SocketAddr,
+
+    /// Address to listen on for HTTPS
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:443")]
+    listen_tls: SocketAddr,
+
+    /// Only proxy plain HTTP
+    #[arg(long)]
+    no_tls: bool,
+}
+
+pub fn run_proxy(proxy: &Proxy) -> Result<ColoredString, Error> {
+    let listener = TcpListener::bind(proxy.listen)?;
+    let tls = if proxy.no_tls {
+        None
+    } else {
+        Some(TcpListener::bind(proxy.listen_tls)?)
+    };
+
+    println!(
+        "{}",
+        format!(
+            "Proxying HTTP requests on {} (Ctrl-C to stop)",
+            listener.local_addr()?
+        )
+        .green()
+    );
+
+    if let Some(tls) = tls {
+        println!(
+            "{}",
+            format!("Proxying HTTPS requests on {}", tls.local_addr()?).green()
+        );
+        let config = tls_config()?;
+        thread::spawn(move || {
+            accept(tls, move |client| {
+                let connection = ServerConnection::new(config.clone())
+                    .map_err(|err| io::Error::other(err.to_string()))?;
+                handle(StreamOwned::new(connection, client))
+            })
+        });
+    }
+
+    accept(listener, handle);
+
+    Ok("Proxy stopped".green())
+}
+
+/// Handles each connection on its own thread.
+fn accept<F>(listener: TcpListener, handle: F)
+where
+    F: Fn(TcpStream) -> io::Result<()> + Clone + Send + 'static,
+{
+    for client in listener.incoming() {
+        let client = match client {
+            Ok(client) => client,
+            Err(err) => {
+                eprintln!("{}", format!("Proxy error: {err}").red());
+                continue;
+            }
+        };
+        let handle = handle.clone();
+        thread::spawn(move || {
+            if let Err(err) = handle(client) {
+                eprintln!("{}", format!("Proxy error: {err}").red());
+            }
+        });
+    }
+}
+
+/// A connection from a client, over TLS or not.
+trait Client: Read + Write {
+    fn tcp(&self) -> &TcpStream;
+    /// The scheme for `X-Forwarded-Proto`
+    fn scheme(&self) -> &'static str;
+    fn close(&mut self);
+}
+
+impl Client for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+
+    fn scheme(&self) -> &'static str {
+        "http"
+    }
+
+    fn close(&mut self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+impl Client for StreamOwned<ServerConnection, TcpStream> {
+    fn tcp(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    fn scheme(&self) -> &'static str {
+        "https"
+    }
+
+    fn close(&mut self) {
+        self.conn.send_close_notify();
+        let _ = self.flush();
+        let _ = self.sock.shutdown(Shutdown::Both);
+    }
+}
+
+/// Forwards a single request, and the connection is closed after the
+/// response so that a keep-alive client can't send its next request, maybe
+/// for another host, to the same app. Upgraded connections, e.g. WebSockets,
+/// are tunneled instead.
+fn handle(client: impl Client) -> io::Result<()> {
+    let peer = client.tcp().peer_addr()?;
+    let scheme = client.scheme();
+    let mut client = BufReader::new(client);
+
+    let Some(head) = read_head(&mut client)? else {
+        return respond(
+            client.get_mut(),
+            "431 Request Header Fields Too Large",
+            "Request head too large\n",
+        );
+    };
+    if head.is_empty() {
+        return Ok(());
+    }
+
+    let header = |wanted: &str| {
+        head[1..].iter().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case(wanted)
+                .then(|| value.trim().to_string())
+        })
+    };
+    let Some(host) = header("host") else {
+        return respond(client.get_mut(), "400 Bad Request", "Missing Host header\n");
+    };
+    let hostname = strip_port(&host);
+    let Some(port) = port_for(hostname)? else {
+        let body = format!(
+            "No port recorded for {hostname}, add one with `hostie add {hostname} --port <PORT>`\n"
+        );
+        return respond(client.get_mut(), "404 Not Found", &body);
+    };
+
+    let mut upstream = match TcpStream::connect(("127.0.0.1", port)) {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            let body = format!("Nothing is listening on port {port} for {hostname}: {err}\n");
+            return respond(client.get_mut(), "502 Bad Gateway", &body);
+        }
+    };
+
+    let upgrade = header("upgrade").is_some()
+        && header("connection").is_some_and(|value| value.to_lowercase().contains("upgrade"));
+    let expects_continue =
+        header("expect").is_some_and(|value| value.eq_ignore_ascii_case("100-continue"));
+
+    let mut forwarded = String::new();
+    for line in &head {
+        let name = line.split_once(':').map(|(name, _)| name.trim());
+        let hop_by_hop = name.is_some_and(|name| {
+            ["proxy-connection", "keep-alive", "expect"]
+                .iter()
+                .any(|h| name.eq_ignore_ascii_case(h))
+                || (!upgrade && name.eq_ignore_ascii_case("connection"))
+        });
+        if !hop_by_hop {
+            forwarded.push_str(line);
+        }
+    }
+    if !upgrade {
+        forwarded.push_str("Connection: close\r\n");
+    }
+    forwarded.push_str(&format!(
+        "X-Forwarded-For: {}\r\nX-Forwarded-Host: {host}\r\nX-Forwarded-Proto: {scheme}\r\n\r\n",
+        peer.ip()
+    ));
+    upstream.write_all(forwarded.as_bytes())?;
+
+    if upgrade {
+        tunnel(&mut client, &mut upstream)?;
+    } else {
+        // The app never sees `Expect`, so it's up to us to let the body come
+        if expects_continue {
+            client
+                .get_mut()
+                .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            client.get_mut().flush()?;
+        }
+        let chunked = header("transfer-encoding")
+            .is_some_and(|value| value.to_lowercase().contains("chunked"));
+        if chunked {
+            copy_chunked(&mut client, &mut upstream)?;
+        } else if let Some(length) = header("content-length") {
+            let length = length.parse().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length")
+            })?;
+            io::copy(&mut (&mut client).take(length), &mut upstream)?;
+        }
+
+        // The app closes the connection after its response, as asked
+        io::copy(&mut upstream, client.get_mut())?;
+    }
+
+    client.get_mut().close();
+    Ok(())
+}
+
+/// The request line and headers, each with its line ending, or `None` if they
+/// don't fit in `MAX_HEAD`. Empty if the client closed the connection first.
+fn read_head(client: &mut impl BufRead) -> io::Result<Option<Vec<String>>> {
+    let mut head = Vec::new();
+    let mut limited = client.take(MAX_HEAD);
+    loop {
+        let mut line = String::new();
+        limited.read_line(&mut line)?;
+        if !line.ends_with('\n') {
+            // Either the client went away, or the head doesn't fit
+            return Ok((limited.limit() > 0).then(Vec::new));
+        }
+        if line.trim().is_empty() {
+            return Ok(Some(head));
+        }
+        head.push(line);
+    }
+}
+
+/// Copies a chunked body as-is, stopping after its last chunk.
+fn copy_chunked(client: &mut impl BufRead, upstream: &mut impl Write) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+        (&mut *client).take(MAX_HEAD).read_line(&mut line)?;
+        upstream.write_all(line.as_bytes())?;
+
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        // The chunk and the line ending after it
+        io::copy(&mut (&mut *client).take(size + 2), upstream)?;
+    }
+
+    // Trailers, up to an empty line
+    loop {
+        let mut line = String::new();
+        if (&mut *client).take(MAX_HEAD).read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        upstream.write_all(line.as_bytes())?;
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// Copies bytes both ways until either side closes. A TLS connection can't be
+/// read and written from two threads, so both sides take turns instead.
+fn tunnel(client: &mut BufReader<impl Client>, upstream: &mut TcpStream) -> io::Result<()> {
+    client.get_ref().tcp().set_read_timeout(Some(TUNNEL_POLL))?;
+    upstream.set_read_timeout(Some(TUNNEL_POLL))?;
+
+    let mut buf = vec![0; 16 * 1024];
+    loop {
+        match client.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => upstream.write_all(&buf[..len])?,
+            Err(err) if is_timeout(&err) => {}
+            Err(err) => return Err(err),
+        }
+        match upstream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => {
+                client.get_mut().write_all(&buf[..len])?;
+                client.get_mut().flush()?;
+            }
+            Err(err) if is_timeout(&err) => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    let _ = upstream.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+fn tls_config() -> Result<Arc<ServerConfig>, Error> {
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| Error::Generic(format!("TLS setup failed: {err}").red()))?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(Certificates::default()));
+    // Only HTTP/1.1 is proxied, so browsers mustn't switch to HTTP/2
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Certificates by the hostname clients ask for, loaded or issued as needed.
+#[derive(Debug, Default)]
+struct Certificates {
+    loaded: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl Certificates {
+    fn load(&self, hostname: &str) -> Result<Arc<CertifiedKey>, Error> {
+        let mut loaded = self.loaded.lock().unwrap_or_else(|p| p.into_inner());
+        if let Some(key) = loaded.get(hostname) {
+            return Ok(key.clone());
+        }
+
+        let (cert_path, key_path) = cert::files(hostname);
+        if !cert_path.exists() || !key_path.exists() {
+            println!("{}", cert::issue(hostname)?.describe().green());
+        }
+
+        let invalid = |err: rustls::pki_types::pem::Error| {
+            Error::Generic(format!("Invalid certificate for {hostname}: {err}").red())
+        };
+        let certs: Vec<CertificateDer<'static>> = CertificateDer::pem_file_iter(&cert_path)
+            .and_then(Iterator::collect)
+            .map_err(invalid)?;
+        let key = PrivateKeyDer::from_pem_file(&key_path).map_err(invalid)?;
+        let key = ring::sign::any_supported_type(&key)
+            .map_err(|err| Error::Generic(format!("Invalid key for {hostname}: {err}").red()))?;
+
+        let key = Arc::new(CertifiedKey::new(certs, key));
+        loaded.insert(hostname.to_string(), key.clone());
+        Ok(key)
+    }
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        // Only names with a recorded port, so that not just any name a client
+        // sends gets a certificate
+        let hostname = client_hello.server_name()?.to_lowercase();
+        if !matches!(port_for(&hostname), Ok(Some(_))) {
+            return None;
+        }
+
+        match self.load(&hostname) {
+            Ok(key) => Some(key),
+            Err(err) => {
+                eprintln!("{err}");
+                None
+            }
+        }
+    }
+}
+
+/// The recorded port for `hostname`. The hosts file is read for every
+/// connection, so `add --port` takes effect without restarting the proxy.
+fn port_for(hostname: &str) -> io::Result<Option<u16>> {
+    let hosts = HostsFile::read(get_hosts_path())?;
+    Ok(hosts
+        .entries()
+        .filter(|entry| {
+            entry
+                .hostnames
+                .iter()
+                .any(|h| h.eq_ignore_ascii_case(hostname))
+        })
+        .find_map(|entry| entry.port))
+}
+
+/// `myapp.test:80` to `myapp.test`, leaving IPv6 literals like `[::1]` intact.
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+fn respond(client: &mut impl Client, status: &str, body: &str) -> io::Result<()> {
+    write!(
+        client,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    client.close();
+    Ok(())
+}
@@ -0,0 +1,133 @@
+//! Entries that last as long as a command.
+//!
+//! `hostie run` marks its entries with its own process, e.g.
+//! `127.0.0.1 api.local # hostie:run=4026531836/4242@81234`, and removes them
+//! when the command exits. Should hostie itself die before it can, the entries
+//! are recognized as leftovers, as no process with that ID and start time
+//! exists, and pruned on the next invocation, see `owner`.
+
+use std::process::{self, Command, ExitStatus};
+
+use clap::Parser;
+use colored::{ColoredString, Colorize};
+
+use crate::hosts::{Entry, HostsFile};
+use crate::owner::Owner;
+use crate::sandbox::{self, parse_mapping};
+use crate::{Error, config, get_hosts_path, update_hosts};
+
+#[derive(Parser)]
+pub struct Run {
+    /// A hostname to add while the command runs, optionally with its IP,
+    /// e.g. `api.local` or `api.local=10.0.0.5` (repeatable)
+    #[arg(long = "host", value_name = "HOSTNAME[=IP]", required = true, value_parser = parse_host)]
+    hosts: Vec<(String, String)>,
+
+    /// Add the entries to a private copy of /etc/hosts instead of the real
+    /// one, like `hostie sandbox` (Linux only)
+    #[arg(long)]
+    sandbox: bool,
+
+    /// The command to run, after `--`
+    #[arg(last = true, required = true, value_name = "COMMAND")]
+    command: Vec<String>,
+}
+
+fn parse_host(value: &str) -> Result<(String, String), String> {
+    let (hostname, ip) = value
+        .split_once('=')
+        .unwrap_or((value, &config().default_ip));
+    parse_mapping(&format!("{ip}={hostname}"))
+}
+
+/// Runs the command with the entries in place and exits with its exit code.
+pub fn run_command(run: &Run) -> Result<ColoredString, Error> {
+    if run.sandbox {
+        let mut hosts = HostsFile::read(get_hosts_path())?;
+        sandbox::add_entries(&mut hosts, &run.hosts);
+        process::exit(sandbox::run_with_hosts(&hosts, &run.command)?);
+    }
+
+    let owner = Owner::current();
+    update_hosts(|hosts| {
+        for (ip, hostname) in &run.hosts {
+            let mut entry = Entry::new(ip, hostname);
+            if hosts
+                .entries()
+                .any(|e| e.has_hostname(hostname) && e.is_ipv6() == entry.is_ipv6())
+            {
+                return Err(Error::Generic(
+                    format!("Entry already exists: {}", hostname.magenta().bold()).red(),
+                ));
+            }
+            entry.run = Some(owner);
+            hosts.push(entry);
+        }
+        Ok(())
+    })?;
+
+    let status = spawn_and_wait(&run.command);
+
+    // Clean up even if the command couldn't be started
+    update_hosts(|hosts| {
+        hosts.remove_entries(|entry| entry.run != Some(owner));
+        Ok(())
+    })?;
+
+    process::exit(sandbox::exit_code(status?))
+}
+
+/// Whether `entry` belongs to a `hostie run` that is gone, e.g. because it
+/// was killed with SIGKILL or the machine rebooted.
+pub fn is_leftover(entry: &Entry) -> bool {
+    entry.run.is_some_and(|owner| !owner.is_running())
+}
+
+#[cfg(unix)]
+fn spawn_and_wait(command: &[String]) -> Result<ExitStatus, Error> {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    // Handling these keeps hostie alive until the command has exited and the
+    // entries are removed
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    let handle = signals.handle();
+
+    let mut child = match Command::new(&command[0]).args(&command[1..]).spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            handle.close();
+            return Err(Error::Generic(
+                format!("Could not start {}: {err}", command[0].yellow().bold()).red(),
+            ));
+        }
+    };
+
+    let child_pid = child.id() as libc::pid_t;
+    let forwarder = std::thread::spawn(move || {
+        for signal in signals.forever() {
+            // Ctrl-C in a terminal already reaches the whole foreground group
+            let from_terminal =
+                signal == SIGINT && unsafe { libc::tcgetpgrp(0) == libc::getpgrp() };
+            if !from_terminal {
+                unsafe { libc::kill(child_pid, signal) };
+            }
+        }
+    });
+
+    let status = child.wait();
+    handle.close();
+    let _ = forwarder.join();
+
+    Ok(status?)
+}
+
+#[cfg(not(unix))]
+fn spawn_and_wait(command: &[String]) -> Result<ExitStatus, Error> {
+    Command::new(&command[0])
+        .args(&command[1..])
+        .status()
+        .map_err(|err| {
+            Error::Generic(format!("Could not start {}: {err}", command[0].yellow().bold()).red())
+        })
+}
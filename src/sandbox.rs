@@ -0,0 +1,195 @@
+//! Running a command with extra entries, without touching the hosts file.
+//!
+//! The command starts in new user and mount namespaces, where a temporary
+//! copy of the hosts file with the extra entries is bind-mounted over
+//! `/etc/hosts`. Unprivileged user namespaces make this work without root;
+//! the command keeps running as the calling user.
+
+use std::fs;
+use std::net::IpAddr;
+use std::process::{self, Command};
+
+use clap::Parser;
+use colored::{ColoredString, Colorize};
+
+use crate::hosts::{Entry, HostsFile, Line, is_hostname};
+use crate::{Error, get_hosts_path};
+
+#[derive(Parser)]
+pub struct Sandbox {
+    /// An entry to add inside the sandbox, e.g. `127.0.0.1=api.example.com`
+    /// (repeatable)
+    #[arg(long = "add", value_name = "IP=HOSTNAME", value_parser = parse_mapping)]
+    entries: Vec<(String, String)>,
+
+    /// The command to run, after `--`
+    #[arg(last = true, required = true, value_name = "COMMAND")]
+    command: Vec<String>,
+}
+
+pub fn parse_mapping(value: &str) -> Result<(String, String), String> {
+    let (ip, hostname) = value
+        .split_once('=')
+        .ok_or("expected IP=HOSTNAME, e.g. 127.0.0.1=api.example.com")?;
+    ip.parse::<IpAddr>()
+        .map_err(|err| format!("invalid IP address {ip:?}: {err}"))?;
+    if !is_hostname(hostname) {
+        return Err(format!("invalid hostname {hostname:?}"));
+    }
+    Ok((ip.to_string(), hostname.to_string()))
+}
+
+/// Runs the command in the sandbox and exits with its exit code.
+pub fn run_sandboxed(sandbox: &Sandbox) -> Result<ColoredString, Error> {
+    let mut hosts = HostsFile::read(get_hosts_path())?;
+    add_entries(&mut hosts, &sandbox.entries);
+
+    let code = run_with_hosts(&hosts, &sandbox.command)?;
+    process::exit(code)
+}
+
+/// Adds `(ip, hostname)` mappings to `hosts`, in place of existing ones for
+/// the same hostnames and address family, as resolvers use the first match.
+pub fn add_entries(hosts: &mut HostsFile, entries: &[(String, String)]) {
+    let entries: Vec<Entry> = entries
+        .iter()
+        .map(|(ip, hostname)| Entry::new(ip, hostname))
+        .collect();
+
+    hosts.lines_mut().retain_mut(|line| {
+        let Line::Entry(existing) = line else {
+            return true;
+        };
+        let is_ipv6 = existing.is_ipv6();
+        existing.hostnames.retain(|hostname| {
+            !entries
+                .iter()
+                .any(|entry| entry.is_ipv6() == is_ipv6 && entry.has_hostname(hostname))
+        });
+        !existing.hostnames.is_empty()
+    });
+    for entry in entries {
+        hosts.push(entry);
+    }
+}
+
+/// Runs `command` with `hosts` mounted over `/etc/hosts`, returning its exit
+/// code.
+pub fn run_with_hosts(hosts: &HostsFile, command: &[String]) -> Result<i32, Error> {
+    // The directory must be new and private, as the copy would otherwise be
+    // written through whatever another user placed at its name in /tmp
+    let dir = std::env::temp_dir().join(format!("hostie-sandbox-{}", process::id()));
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(&dir)?;
+    let copy = dir.join("hosts");
+    if let Err(err) = fs::write(&copy, hosts.to_string()) {
+        let _ = fs::remove_dir_all(&dir);
+        return Err(err.into());
+    }
+
+    let mut cmd = Command::new(&command[0]);
+    cmd.args(&command[1..]);
+    let spawned = enter_namespaces(&mut cmd, &copy).and_then(|()| Ok(cmd.spawn()?));
+
+    // Once mounted the copy lives on in the sandbox, its name isn't needed
+    let _ = fs::remove_dir_all(&dir);
+
+    let status = spawned
+        .map_err(|err| {
+            Error::Generic(
+                format!(
+                    "Could not start {} in a sandbox: {err}. Unprivileged user namespaces may be disabled on this system",
+                    command[0].yellow().bold()
+                )
+                .red(),
+            )
+        })?
+        .wait()?;
+
+    Ok(exit_code(status))
+}
+
+#[cfg(target_os = "linux")]
+fn enter_namespaces(cmd: &mut Command, copy: &std::path::Path) -> Result<(), Error> {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::process::CommandExt;
+
+    // Everything is prepared up front, as only async-signal-safe calls are
+    // allowed between fork and exec
+    let copy = CString::new(copy.as_os_str().as_bytes()).expect("temp paths have no NUL");
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    let uid_map = format!("{uid} {uid} 1");
+    let gid_map = format!("{gid} {gid} 1");
+
+    fn check(result: libc::c_int) -> io::Result<()> {
+        if result == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn write_file(path: &std::ffi::CStr, contents: &[u8]) -> io::Result<()> {
+        unsafe {
+            let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            check(fd)?;
+            let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+            libc::close(fd);
+            if written == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    unsafe {
+        cmd.pre_exec(move || {
+            check(libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNS))?;
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/uid_map", uid_map.as_bytes())?;
+            write_file(c"/proc/self/gid_map", gid_map.as_bytes())?;
+
+            // Keep the mount from propagating back to the real /etc/hosts
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            check(libc::mount(
+                copy.as_ptr(),
+                c"/etc/hosts".as_ptr(),
+                std::ptr::null(),
+                libc::MS_BIND,
+                std::ptr::null(),
+            ))
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn enter_namespaces(_cmd: &mut Command, _copy: &std::path::Path) -> Result<(), Error> {
+    Err(Error::Generic(
+        "Sandboxes are only supported on Linux".red(),
+    ))
+}
+
+/// The exit code of a finished command, `128 + signal` if it was killed the
+/// way shells report it.
+pub fn exit_code(status: process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
@@ -0,0 +1,754 @@
+//! An interactive editor for the entries in the hosts file.
+//!
+//! Changes are made to a copy in memory. Nothing is written until the
+//! difference has been previewed and confirmed, and then through the same
+//! path as every other command, backups included. Disabled entries are
+//! commented out, e.g. `# 127.0.0.1 app.local`, so they can be enabled again.
+
+use std::fs;
+use std::io::IsTerminal;
+use std::net::IpAddr;
+
+use colored::{ColoredString, Colorize};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line as TextLine, Span};
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::hosts::{Entry, HostsFile, Line, is_hostname};
+use crate::{Error, blocklist, config, get_hosts_path, update_hosts};
+
+pub fn run_tui() -> Result<ColoredString, Error> {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Err(Error::Generic("hostie tui needs a terminal".red()));
+    }
+
+    let path = get_hosts_path();
+    let mut app = App::new(path.clone(), fs::read_to_string(&path)?);
+
+    let mut terminal = ratatui::try_init()?;
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result?;
+
+    if app.saved {
+        Ok(format!("Saved changes to {path}").green())
+    } else {
+        Ok("No changes saved".green())
+    }
+}
+
+enum Mode {
+    Browse,
+    Filter,
+    Edit(String),
+    /// Showing the difference to the file, scrolled down this many lines
+    Preview(u16),
+    /// Waiting for a second `q` to throw away unsaved changes
+    ConfirmQuit,
+}
+
+struct App {
+    path: String,
+    hosts: HostsFile,
+    /// The file as hostie would write it unchanged, to notice other programs
+    /// changing it
+    unchanged: String,
+    /// Whether `hosts` was changed since it was read or saved
+    changed: bool,
+    filter: String,
+    /// Indexes into the lines of `hosts` of the entries shown
+    rows: Vec<usize>,
+    table: TableState,
+    mode: Mode,
+    status: Option<(String, bool)>,
+    saved: bool,
+    quit: bool,
+}
+
+impl App {
+    fn new(path: String, contents: String) -> Self {
+        let hosts = HostsFile::parse(&contents);
+        let mut app = App {
+            path,
+            unchanged: hosts.to_string(),
+            hosts,
+            changed: false,
+            filter: String::new(),
+            rows: Vec::new(),
+            table: TableState::default().with_selected(0),
+            mode: Mode::Browse,
+            status: None,
+            saved: false,
+            quit: false,
+        };
+        app.refresh();
+        app
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Error> {
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    self.handle_key(key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The line index of the selected entry.
+    fn selected(&self) -> Option<usize> {
+        self.rows.get(self.table.selected()?).copied()
+    }
+
+    /// Works out which entries to show after the file or filter changed,
+    /// leaving out blocklists, which run to 100k+ entries.
+    fn refresh(&mut self) {
+        let filter = self.filter.to_lowercase();
+        self.rows = self
+            .hosts
+            .lines_by_block()
+            .enumerate()
+            .filter(|(_, (block, _))| !blocklist::is_blocklist_block(*block))
+            .filter_map(|(index, (_, line))| {
+                let (entry, _) = row_entry(line)?;
+                let matches = entry.ip.contains(&filter)
+                    || entry
+                        .hostnames
+                        .iter()
+                        .any(|hostname| hostname.to_lowercase().contains(&filter));
+                matches.then_some(index)
+            })
+            .collect();
+
+        let selected = self.table.selected().unwrap_or(0);
+        self.table
+            .select(Some(selected.min(self.rows.len().saturating_sub(1))));
+    }
+
+    fn set_status(&mut self, message: impl Into<String>) {
+        self.status = Some((message.into(), false));
+    }
+
+    fn set_error(&mut self, message: impl Into<String>) {
+        self.status = Some((message.into(), true));
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        let ctrl_c =
+            key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c');
+
+        match &mut self.mode {
+            Mode::Browse | Mode::ConfirmQuit if ctrl_c => self.quit = true,
+            Mode::Browse => self.handle_browse_key(key.code),
+            Mode::ConfirmQuit => match key.code {
+                KeyCode::Char('q') => self.quit = true,
+                KeyCode::Char('s') => {
+                    self.mode = Mode::Preview(0);
+                    self.status = None;
+                }
+                _ => {
+                    self.mode = Mode::Browse;
+                    self.status = None;
+                }
+            },
+            Mode::Filter => match key.code {
+                KeyCode::Enter => self.mode = Mode::Browse,
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.mode = Mode::Browse;
+                    self.refresh();
+                }
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                    self.refresh();
+                }
+                KeyCode::Char(c) => {
+                    self.filter.push(c);
+                    self.refresh();
+                }
+                _ => {}
+            },
+            Mode::Edit(input) => match key.code {
+                KeyCode::Enter => {
+                    let input = input.clone();
+                    self.mode = Mode::Browse;
+                    self.apply_edit(&input);
+                }
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            },
+            Mode::Preview(scroll) => match key.code {
+                KeyCode::Char('y') | KeyCode::Enter => {
+                    self.mode = Mode::Browse;
+                    self.save();
+                }
+                KeyCode::Char('n') | KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Down | KeyCode::Char('j') => *scroll = scroll.saturating_add(1),
+                KeyCode::Up | KeyCode::Char('k') => *scroll = scroll.saturating_sub(1),
+                _ => {}
+            },
+        }
+    }
+
+    fn handle_browse_key(&mut self, code: KeyCode) {
+        self.status = None;
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc if self.changed => {
+                self.mode = Mode::ConfirmQuit;
+                self.set_error("Unsaved changes: press q again to discard them, s to save");
+            }
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+            KeyCode::PageDown => self.table.scroll_down_by(20),
+            KeyCode::PageUp => self.table.scroll_up_by(20),
+            KeyCode::Home | KeyCode::Char('g') => self.table.select_first(),
+            KeyCode::End | KeyCode::Char('G') => self.table.select_last(),
+            KeyCode::Char('/') => self.mode = Mode::Filter,
+            KeyCode::Char(' ') => self.toggle(),
+            KeyCode::Char('e') | KeyCode::Enter => {
+                if let Some((entry, _)) = self.selected().and_then(|index| self.row(index)) {
+                    self.mode = Mode::Edit(format!("{} {}", entry.ip, entry.hostnames.join(" ")));
+                }
+            }
+            KeyCode::Char('d') | KeyCode::Delete => self.delete(),
+            KeyCode::Char('s') if self.changed => self.mode = Mode::Preview(0),
+            KeyCode::Char('s') => self.set_status("No changes to save"),
+            _ => {}
+        }
+    }
+
+    fn row(&self, index: usize) -> Option<(Entry, bool)> {
+        row_entry(&self.hosts.lines()[index])
+    }
+
+    /// A protected hostname of the entry, which may not go away.
+    fn protected(entry: &Entry) -> Option<&String> {
+        entry
+            .hostnames
+            .iter()
+            .find(|hostname| config().protected.contains(hostname))
+    }
+
+    /// A hostname of `candidate` that an enabled entry other than the one at
+    /// `index` already has, for the same address family.
+    fn taken<'a>(&self, index: usize, candidate: &'a Entry) -> Option<&'a String> {
+        candidate.hostnames.iter().find(|hostname| {
+            self.hosts.lines().iter().enumerate().any(|(i, line)| {
+                i != index
+                    && matches!(line, Line::Entry(entry)
+                        if entry.has_hostname(hostname) && entry.is_ipv6() == candidate.is_ipv6())
+            })
+        })
+    }
+
+    fn toggle(&mut self) {
+        let Some(index) = self.selected() else {
+            return;
+        };
+        let Some((entry, enabled)) = self.row(index) else {
+            return;
+        };
+
+        if enabled {
+            if let Some(hostname) = Self::protected(&entry) {
+                self.set_error(format!("Cannot disable protected entry: {hostname}"));
+                return;
+            }
+            self.hosts.lines_mut()[index] = entry.disable();
+        } else {
+            if let Some(hostname) = self.taken(index, &entry) {
+                self.set_error(format!("Entry already exists: {hostname}"));
+                return;
+            }
+            self.hosts.lines_mut()[index] = Line::Entry(entry);
+        }
+        self.changed = true;
+    }
+
+    fn apply_edit(&mut self, input: &str) {
+        let Some(index) = self.selected() else {
+            return;
+        };
+        let Some((mut entry, enabled)) = self.row(index) else {
+            return;
+        };
+
+        let mut fields = input.split_whitespace().map(String::from);
+        let ip = fields.next().unwrap_or_default();
+        let hostnames: Vec<String> = fields.collect();
+        if ip.parse::<IpAddr>().is_err() {
+            self.set_error(format!("Not a valid IP address: {ip}"));
+            return;
+        }
+        if hostnames.is_empty() {
+            self.set_error("An entry needs at least one hostname");
+            return;
+        }
+        if let Some(hostname) = hostnames.iter().find(|hostname| !is_hostname(hostname)) {
+            self.set_error(format!("Not a valid hostname: {hostname}"));
+            return;
+        }
+        if let Some(hostname) = Self::protected(&entry).filter(|h| !hostnames.contains(h)) {
+            self.set_error(format!("Cannot remove protected hostname: {hostname}"));
+            return;
+        }
+
+        // Comments, tags and the like stay with the entry
+        entry.ip = ip;
+        entry.hostnames = hostnames;
+        if enabled {
+            if let Some(hostname) = self.taken(index, &entry) {
+                self.set_error(format!("Entry already exists: {hostname}"));
+                return;
+            }
+        }
+        self.hosts.lines_mut()[index] = if enabled {
+            Line::Entry(entry)
+        } else {
+            entry.disable()
+        };
+        self.changed = true;
+        self.refresh();
+    }
+
+    fn delete(&mut self) {
+        let Some(index) = self.selected() else {
+            return;
+        };
+        let Some((entry, _)) = self.row(index) else {
+            return;
+        };
+
+        if let Some(hostname) = Self::protected(&entry) {
+            self.set_error(format!("Cannot remove protected entry: {hostname}"));
+            return;
+        }
+        self.hosts.lines_mut().remove(index);
+        self.changed = true;
+        self.refresh();
+    }
+
+    fn save(&mut self) {
+        let saved = update_hosts(|current| {
+            // Someone else's changes would silently be lost otherwise
+            if current.to_string() != self.unchanged {
+                return Ok(false);
+            }
+            *current = self.hosts.clone();
+            Ok(true)
+        });
+
+        match saved {
+            Ok(true) => {
+                self.unchanged = self.hosts.to_string();
+                self.changed = false;
+                self.saved = true;
+                self.set_status(format!("Saved {}", self.path));
+            }
+            Ok(false) => {
+                self.set_error("The hosts file changed since it was opened, quit and start over")
+            }
+            Err(err) => self.set_error(format!("Could not save: {err}")),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status, help] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        if let Mode::Preview(scroll) = self.mode {
+            frame.render_widget(self.preview().scroll((scroll, 0)), main);
+        } else {
+            let table = self.entry_table();
+            frame.render_stateful_widget(table, main, &mut self.table);
+        }
+
+        let status_line = match (&self.mode, &self.status) {
+            (Mode::Filter, _) => TextLine::from(format!("/{}", self.filter)),
+            (Mode::Edit(input), _) => TextLine::from(vec![
+                Span::styled(
+                    "IP and hostnames: ",
+                    Style::new().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(input.as_str()),
+            ]),
+            (_, Some((message, is_error))) => {
+                let color = if *is_error { Color::Red } else { Color::Green };
+                TextLine::styled(message.as_str(), Style::new().fg(color))
+            }
+            (_, None) if !self.filter.is_empty() => {
+                TextLine::from(format!("Filter: {}", self.filter))
+            }
+            (_, None) => TextLine::default(),
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+
+        let keys = match self.mode {
+            Mode::Browse | Mode::ConfirmQuit => {
+                "↑↓ move  / filter  space enable/disable  e edit  d delete  s save  q quit"
+            }
+            Mode::Filter => "type to filter  enter keep filter  esc clear filter",
+            Mode::Edit(_) => "enter apply  esc cancel",
+            Mode::Preview(_) => "↑↓ scroll  y write to the hosts file  n back",
+        };
+        frame.render_widget(
+            Paragraph::new(keys).style(Style::new().fg(Color::DarkGray)),
+            help,
+        );
+    }
+
+    fn entry_table(&self) -> Table<'static> {
+        let rows: Vec<Row> = self
+            .rows
+            .iter()
+            .filter_map(|&index| self.row(index))
+            .map(|(entry, enabled)| {
+                let mut annotations = Vec::new();
+                if let Some(comment) = &entry.comment {
+                    annotations.push(comment.clone());
+                }
+                if !entry.tags.is_empty() {
+                    annotations.push(format!("[{}]", entry.tags.join(", ")));
+                }
+
+                let (state, ip, hostnames) = if enabled {
+                    (
+                        Style::new(),
+                        Style::new().fg(Color::Cyan),
+                        Style::new().fg(Color::Magenta),
+                    )
+                } else {
+                    let off = Style::new().fg(Color::DarkGray);
+                    (off, off, off)
+                };
+                Row::new(vec![
+                    Cell::from(if enabled { "on" } else { "off" }).style(state),
+                    Cell::from(entry.ip.clone()).style(ip),
+                    Cell::from(entry.hostnames.join(" ")).style(hostnames),
+                    Cell::from(annotations.join(" ")).style(state),
+                ])
+            })
+            .collect();
+
+        let changed = if self.changed { " (modified)" } else { "" };
+        let ip_width = self
+            .rows
+            .iter()
+            .filter_map(|&index| self.row(index))
+            .map(|(entry, _)| entry.ip.len())
+            .max()
+            .unwrap_or(0)
+            .max(2) as u16;
+
+        Table::new(
+            rows,
+            [
+                Constraint::Length(3),
+                Constraint::Length(ip_width),
+                Constraint::Fill(2),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(
+            Row::new(["", "IP", "Hostnames", "Comment"])
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered().title(format!(" {}{changed} ", self.path)))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+    }
+
+    /// The lines removed and added by saving.
+    fn preview(&self) -> Paragraph<'static> {
+        let before: Vec<String> = self.unchanged.lines().map(String::from).collect();
+        let after: Vec<String> = self.hosts.to_string().lines().map(String::from).collect();
+
+        let lines: Vec<TextLine> = line_diff(&before, &after)
+            .into_iter()
+            .map(|(sign, line)| {
+                let color = if sign == '-' {
+                    Color::Red
+                } else {
+                    Color::Green
+                };
+                TextLine::styled(format!("{sign} {line}"), Style::new().fg(color))
+            })
+            .collect();
+
+        Paragraph::new(lines).block(Block::bordered().title(" Changes to save "))
+    }
+}
+
+/// The largest table `line_diff` builds, in lines before times lines after
+/// the change, about 16 MB.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// The lines removed from `before` (`-`) and added in `after` (`+`), in file
+/// order. Unlike `watch::diff`, which only cares about entries, this also
+/// shows lines that moved or were duplicated.
+fn line_diff<'a>(before: &'a [String], after: &'a [String]) -> Vec<(char, &'a str)> {
+    // Changes are usually few and close together, so only the part between
+    // the common beginning and end needs a table
+    let prefix = before.iter().zip(after).take_while(|(a, b)| a == b).count();
+    let (before, after) = (&before[prefix..], &after[prefix..]);
+    let suffix = before
+        .iter()
+        .rev()
+        .zip(after.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (before, after) = (
+        &before[..before.len() - suffix],
+        &after[..after.len() - suffix],
+    );
+
+    if before.len().saturating_mul(after.len()) > MAX_DIFF_CELLS {
+        return before
+            .iter()
+            .map(|line| ('-', line.as_str()))
+            .chain(after.iter().map(|line| ('+', line.as_str())))
+            .collect();
+    }
+
+    // The length of the longest common subsequence of `before[i..]` and
+    // `after[j..]` at `i * width + j`
+    let width = after.len() + 1;
+    let mut common = vec![0u32; (before.len() + 1) * width];
+    for i in (0..before.len()).rev() {
+        for j in (0..after.len()).rev() {
+            common[i * width + j] = if before[i] == after[j] {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < before.len() || j < after.len() {
+        if i < before.len() && j < after.len() && before[i] == after[j] {
+            i += 1;
+            j += 1;
+        } else if j == after.len()
+            || (i < before.len() && common[(i + 1) * width + j] >= common[i * width + j + 1])
+        {
+            changes.push(('-', before[i].as_str()));
+            i += 1;
+        } else {
+            changes.push(('+', after[j].as_str()));
+            j += 1;
+        }
+    }
+    changes
+}
+
+/// The entry on a line and whether it is enabled, `None` for other lines.
+fn row_entry(line: &Line) -> Option<(Entry, bool)> {
+    match line {
+        Line::Entry(entry) => Some((entry.clone(), true)),
+        Line::Other(_) => line.disabled_entry().map(|entry| (entry, false)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTS: &str = "\
+127.0.0.1 localhost
+# 10.0.0.1 off.local
+127.0.0.1 app.local
+";
+
+    fn app(contents: &str) -> App {
+        App::new("/etc/hosts".to_string(), contents.to_string())
+    }
+
+    fn press(app: &mut App, code: KeyCode) {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        for c in text.chars() {
+            press(app, KeyCode::Char(c));
+        }
+    }
+
+    /// Opens the editor on the selected entry and replaces its text.
+    fn edit(app: &mut App, text: &str) {
+        press(app, KeyCode::Char('e'));
+        let Mode::Edit(input) = &mut app.mode else {
+            panic!("not editing");
+        };
+        input.clear();
+        type_text(app, text);
+        press(app, KeyCode::Enter);
+    }
+
+    /// Selects the last entry, without `End`, which relies on drawing to
+    /// clamp the selection.
+    fn select_last(app: &mut App) {
+        for _ in 1..app.rows.len() {
+            press(app, KeyCode::Down);
+        }
+    }
+
+    fn error(app: &App) -> Option<&str> {
+        match &app.status {
+            Some((message, true)) => Some(message),
+            _ => None,
+        }
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn toggles_entries_off_and_on() {
+        let mut app = app(HOSTS);
+        select_last(&mut app);
+        press(&mut app, KeyCode::Char(' '));
+        assert!(app.changed);
+        assert_eq!(
+            app.hosts.to_string(),
+            "127.0.0.1 localhost\n# 10.0.0.1 off.local\n# 127.0.0.1 app.local\n"
+        );
+
+        press(&mut app, KeyCode::Char(' '));
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(error(&app), None);
+        assert_eq!(
+            app.hosts.to_string(),
+            "127.0.0.1 localhost\n10.0.0.1 off.local\n127.0.0.1 app.local\n"
+        );
+    }
+
+    #[test]
+    fn refuses_to_enable_a_hostname_twice() {
+        let mut app = app("# 10.0.0.1 app.local\n127.0.0.1 app.local\n");
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(error(&app), Some("Entry already exists: app.local"));
+        assert!(!app.changed);
+    }
+
+    #[test]
+    fn refuses_to_disable_or_delete_protected_entries() {
+        let mut app = app(HOSTS);
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(
+            error(&app),
+            Some("Cannot disable protected entry: localhost")
+        );
+        press(&mut app, KeyCode::Char('d'));
+        assert_eq!(
+            error(&app),
+            Some("Cannot remove protected entry: localhost")
+        );
+        edit(&mut app, "127.0.0.1 renamed.local");
+        assert_eq!(
+            error(&app),
+            Some("Cannot remove protected hostname: localhost")
+        );
+
+        assert!(!app.changed);
+        assert_eq!(app.hosts.to_string(), HOSTS);
+    }
+
+    #[test]
+    fn deletes_entries() {
+        let mut app = app(HOSTS);
+        select_last(&mut app);
+        press(&mut app, KeyCode::Char('d'));
+        assert!(app.changed);
+        assert_eq!(
+            app.hosts.to_string(),
+            "127.0.0.1 localhost\n# 10.0.0.1 off.local\n"
+        );
+        assert_eq!(app.rows, [0, 1]);
+    }
+
+    #[test]
+    fn rejects_invalid_edits() {
+        let mut app = app(HOSTS);
+        select_last(&mut app);
+        for (input, message) in [
+            ("not-an-ip app.local", "Not a valid IP address: not-an-ip"),
+            ("127.0.0.1", "An entry needs at least one hostname"),
+            ("127.0.0.1 bad_host!", "Not a valid hostname: bad_host!"),
+            ("127.0.0.1 localhost", "Entry already exists: localhost"),
+        ] {
+            edit(&mut app, input);
+            assert_eq!(error(&app), Some(message), "{input}");
+        }
+
+        assert!(!app.changed);
+        assert_eq!(app.hosts.to_string(), HOSTS);
+    }
+
+    #[test]
+    fn applies_valid_edits() {
+        let mut app = app(HOSTS);
+        press(&mut app, KeyCode::Down);
+        edit(&mut app, "10.0.0.2 off.local other.local");
+        press(&mut app, KeyCode::Down);
+        edit(&mut app, "::1 app.local");
+        assert_eq!(error(&app), None);
+        assert!(app.changed);
+        // Disabled entries stay disabled
+        assert_eq!(
+            app.hosts.to_string(),
+            "127.0.0.1 localhost\n# 10.0.0.2 off.local other.local\n::1 app.local\n"
+        );
+    }
+
+    #[test]
+    fn asks_before_quitting_with_unsaved_changes() {
+        let mut app = app(HOSTS);
+        press(&mut app, KeyCode::Char('s'));
+        assert!(matches!(app.mode, Mode::Browse));
+        assert_eq!(app.status, Some(("No changes to save".to_string(), false)));
+
+        select_last(&mut app);
+        press(&mut app, KeyCode::Char('d'));
+        press(&mut app, KeyCode::Char('q'));
+        assert!(matches!(app.mode, Mode::ConfirmQuit));
+        assert!(!app.quit);
+        press(&mut app, KeyCode::Char('q'));
+        assert!(app.quit);
+    }
+
+    #[test]
+    fn diffs_lines_in_file_order() {
+        let before = lines("a\nb\nc\nd");
+        let after = lines("a\nx\nc\ny\nd");
+        assert_eq!(
+            line_diff(&before, &after),
+            [('-', "b"), ('+', "x"), ('+', "y")]
+        );
+    }
+
+    #[test]
+    fn diffs_moved_and_duplicated_lines() {
+        let before = lines("a\nb");
+        assert_eq!(line_diff(&before, &lines("b\na")), [('-', "a"), ('+', "a")]);
+        assert_eq!(line_diff(&before, &lines("a\nb\na")), [('+', "a")]);
+        assert_eq!(line_diff(&lines("a\na\nb"), &before), [('-', "a")]);
+        assert_eq!(line_diff(&before, &before), []);
+    }
+}
@@ -0,0 +1,133 @@
+//! Watching the hosts file for changes made behind hostie's back.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::SystemTime;
+
+use colored::{ColoredString, Colorize};
+use notify::event::{AccessKind, AccessMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::hosts::HostsFile;
+use crate::{Error, get_hosts_path};
+
+/// Prints the entries added to and removed from the hosts file each time it
+/// changes, until interrupted.
+pub fn watch_hosts_file() -> Result<ColoredString, Error> {
+    let path = get_hosts_path();
+    let mut previous = entry_lines(&path);
+
+    on_change(
+        Path::new(&path),
+        || {
+            println!(
+                "{}",
+                format!("Watching {path} for changes (Ctrl-C to stop)").green()
+            );
+            Ok(())
+        },
+        || {
+            let current = entry_lines(&path);
+            let (removed, added) = diff(&previous, &current);
+
+            let timestamp = humantime::format_rfc3339_seconds(SystemTime::now());
+            for line in removed {
+                println!("{} {} {}", timestamp, "-".red().bold(), line.red());
+            }
+            for line in added {
+                println!("{} {} {}", timestamp, "+".green().bold(), line.green());
+            }
+
+            previous = current;
+            Ok(())
+        },
+    )?;
+
+    Ok("Stopped watching hosts file".green())
+}
+
+/// Calls `changed` every time the file at `path` is written, replaced or
+/// removed, until `changed` fails. `ready` is called once the watch is in
+/// place, so nothing that happens after it can be missed.
+///
+/// The directory is watched rather than the file itself, so that the watch
+/// survives editors and tools that replace the file instead of writing to it.
+pub fn on_change(
+    path: &Path,
+    ready: impl FnOnce() -> Result<(), Error>,
+    mut changed: impl FnMut() -> Result<(), Error>,
+) -> Result<(), Error> {
+    let path = resolve(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    ready()?;
+
+    for event in rx {
+        let event = event?;
+
+        // Reading the file, as we do ourselves, shows up as access events
+        let is_write = match event.kind {
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+            EventKind::Access(_) => false,
+            _ => true,
+        };
+        let is_hosts_file = event
+            .paths
+            .iter()
+            .any(|p| p.file_name() == path.file_name());
+
+        if is_write && is_hosts_file {
+            changed()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Lines only in `previous` and lines only in `current`, in file order.
+pub fn diff<'a>(
+    previous: &'a [String],
+    current: &'a [String],
+) -> (Vec<&'a String>, Vec<&'a String>) {
+    let previous_set: HashSet<_> = previous.iter().collect();
+    let current_set: HashSet<_> = current.iter().collect();
+
+    let removed = previous
+        .iter()
+        .filter(|line| !current_set.contains(line))
+        .collect();
+    let added = current
+        .iter()
+        .filter(|line| !previous_set.contains(line))
+        .collect();
+
+    (removed, added)
+}
+
+/// Resolves symlinks, so that e.g. a hosts file managed by the system
+/// configuration and linked into /etc is watched where it really lives.
+fn resolve(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The entries in the file at `path`, normalized to `ip hostname...`, or
+/// nothing while the file is missing or unreadable.
+fn entry_lines(path: &str) -> Vec<String> {
+    let hosts = HostsFile::read(path).unwrap_or_default();
+
+    let mut seen = HashSet::new();
+    hosts
+        .entries()
+        .map(|entry| format!("{} {}", entry.ip, entry.hostnames.join(" ")))
+        .filter(|line| seen.insert(line.clone()))
+        .collect()
+}
@@ -0,0 +1,247 @@
+//! Finding the hostnames served by local web server configs.
+//!
+//! Understands nginx `server_name`, Apache `ServerName`/`ServerAlias` and
+//! Caddyfile site addresses. Names hosts files can't express, such as
+//! wildcards, regular expressions and catch-alls, are ignored.
+
+use std::collections::HashSet;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use colored::{ColoredString, Colorize};
+
+use crate::hosts::{Entry, HostsFile, is_hostname};
+use crate::{Error, get_hosts_path, update_hosts};
+
+#[derive(Parser)]
+pub struct ScanWebConfig {
+    /// A config file, or a directory to search, e.g. /etc/nginx
+    #[arg(value_name = "PATH")]
+    path: PathBuf,
+
+    /// Write the proposed entries instead of only printing them
+    #[arg(long)]
+    apply: bool,
+
+    /// The IP address to point the hostnames at
+    #[arg(long, default_value = "127.0.0.1")]
+    ip: String,
+}
+
+pub fn scan_web_config(scan: &ScanWebConfig) -> Result<ColoredString, Error> {
+    let mut files = Vec::new();
+    collect_files(&scan.path, &mut files, &mut HashSet::new())?;
+    files.sort();
+
+    let hosts = HostsFile::read(get_hosts_path())?;
+
+    let mut proposed: Vec<(String, PathBuf)> = Vec::new();
+    for file in files {
+        // Binary files and the like are no configs
+        let Ok(contents) = fs::read_to_string(&file) else {
+            continue;
+        };
+        for name in server_names(&file, &contents) {
+            let known = hosts.entries().any(|entry| entry.has_hostname(&name))
+                || proposed.iter().any(|(n, _)| *n == name);
+            if !known {
+                proposed.push((name, file.clone()));
+            }
+        }
+    }
+
+    if proposed.is_empty() {
+        return Ok("All server names already have an entry".green());
+    }
+
+    if !scan.apply {
+        let mut lines = vec!["Proposed entries (run with --apply to write them):".to_string()];
+        lines.extend(proposed.iter().map(|(name, file)| {
+            format!(
+                "{} {}  ({})",
+                scan.ip.cyan().bold(),
+                name.magenta().bold(),
+                file.display()
+            )
+        }));
+        return Ok(lines.join("\n").green());
+    }
+
+    // Unless another entry was added in the meantime
+    let proposed = update_hosts(|hosts| {
+        proposed.retain(|(name, _)| !hosts.entries().any(|entry| entry.has_hostname(name)));
+        for (name, _) in &proposed {
+            hosts.push(Entry::new(&scan.ip, name));
+        }
+        Ok(proposed)
+    })?;
+
+    let lines: Vec<String> = proposed
+        .iter()
+        .map(|(name, _)| {
+            format!(
+                "Added entry to hosts file: {} {}",
+                scan.ip.cyan().bold(),
+                name.magenta().bold()
+            )
+        })
+        .collect();
+
+    Ok(lines.join("\n").green())
+}
+
+/// The files in and below `path`. Directories are only searched once, so
+/// that symlinks like `sites-enabled/self -> .` don't loop forever.
+fn collect_files(
+    path: &Path,
+    files: &mut Vec<PathBuf>,
+    visited: &mut HashSet<PathBuf>,
+) -> Result<(), Error> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    if !visited.insert(fs::canonicalize(path)?) {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(path)? {
+        collect_files(&entry?.path(), files, visited)?;
+    }
+    Ok(())
+}
+
+fn server_names(file: &Path, contents: &str) -> Vec<String> {
+    let is_caddyfile = file.file_name().is_some_and(|name| {
+        let name = name.to_string_lossy().to_lowercase();
+        name == "caddyfile" || name.ends_with(".caddyfile") || name.ends_with(".caddy")
+    });
+
+    let names = if is_caddyfile {
+        caddy_addresses(contents)
+    } else {
+        let mut names = nginx_server_names(contents);
+        names.extend(apache_server_names(contents));
+        names
+    };
+
+    names.iter().filter_map(|name| normalize(name)).collect()
+}
+
+/// Arguments of `server_name` directives, which may span several lines.
+fn nginx_server_names(contents: &str) -> Vec<String> {
+    let contents = strip_comments(contents);
+
+    contents
+        .split(';')
+        .filter_map(|statement| {
+            // Only the part after the last block boundary is the directive
+            let directive = statement.rsplit(['{', '}']).next().unwrap_or_default();
+            let mut words = directive.split_whitespace();
+            (words.next() == Some("server_name")).then(|| words.map(String::from).collect())
+        })
+        .flat_map(|names: Vec<String>| names)
+        .collect()
+}
+
+fn apache_server_names(contents: &str) -> Vec<String> {
+    strip_comments(contents)
+        .lines()
+        .flat_map(|line| {
+            let mut words = line.split_whitespace();
+            let directive = words.next().unwrap_or_default();
+            if directive.eq_ignore_ascii_case("ServerName")
+                || directive.eq_ignore_ascii_case("ServerAlias")
+            {
+                words.map(String::from).collect()
+            } else {
+                Vec::new()
+            }
+        })
+        .collect()
+}
+
+/// Site addresses: what comes before a top-level `{`, or the first line of a
+/// Caddyfile that serves a single site without braces.
+fn caddy_addresses(contents: &str) -> Vec<String> {
+    let contents = strip_comments(contents);
+    let lines: Vec<&str> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    if !contents.contains('{') {
+        return lines
+            .first()
+            .map(|line| split_addresses(line))
+            .unwrap_or_default();
+    }
+
+    let mut addresses = Vec::new();
+    let mut depth = 0usize;
+    let mut pending = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if depth == 0 {
+            let head = line.split('{').next().unwrap_or_default();
+            pending.push(' ');
+            pending.push_str(head);
+            let continues =
+                line.ends_with(',') || lines.get(i + 1).is_some_and(|next| next.starts_with('{'));
+            if line.contains('{') {
+                // Snippets are `(name) { ... }`, global options a bare `{`
+                if !pending.trim().starts_with('(') {
+                    addresses.extend(split_addresses(&pending));
+                }
+                pending.clear();
+            } else if !continues {
+                pending.clear();
+            }
+        }
+        for c in line.chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+    }
+
+    addresses
+}
+
+fn split_addresses(line: &str) -> Vec<String> {
+    line.split([',', ' ', '\t'])
+        .filter(|address| !address.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Turns e.g. `https://Shop.test:8443/` into `shop.test`, or `None` for names
+/// that have no place in a hosts file.
+fn normalize(name: &str) -> Option<String> {
+    let name = name.trim_matches(|c| c == '"' || c == '\'');
+    let name = name.split_once("://").map_or(name, |(_, rest)| rest);
+    let name = name.split('/').next().unwrap_or_default();
+    let name = match name.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => name,
+    };
+    let name = name.trim_end_matches('.').to_lowercase();
+
+    // A leading dot is nginx shorthand for a wildcard
+    let is_special =
+        ["localhost", "_", "default"].contains(&name.as_str()) || name.starts_with('.');
+    let is_ip = name.parse::<IpAddr>().is_ok();
+    (!is_special && !is_ip && is_hostname(&name)).then_some(name)
+}
+
+fn strip_comments(contents: &str) -> String {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
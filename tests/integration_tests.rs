@@ -1,10 +1,19 @@
 use std::fs;
 use std::process::Command;
-use tempfile::NamedTempFile;
+use std::sync::OnceLock;
+use tempfile::{NamedTempFile, TempDir};
 
-/// Helper to create a hostie command
+/// Helper to create a hostie command, which reads no config file unless a
+/// test sets `HOSTIE_CONFIG` itself
 fn hostie_command() -> Command {
-    Command::new(env!("CARGO_BIN_EXE_hostie"))
+    // A path in a directory of our own that never has a config file in it,
+    // so the developer's own config can't change what the tests see
+    static NO_CONFIG: OnceLock<TempDir> = OnceLock::new();
+    let dir = NO_CONFIG.get_or_init(|| tempfile::tempdir().unwrap());
+
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_hostie"));
+    cmd.env("HOSTIE_CONFIG", dir.path().join("config.toml"));
+    cmd
 }
 
 /// Helper to create a hostie command with a custom hosts file
@@ -136,6 +145,45 @@ fn test_add_duplicate_hostname() {
     assert!(stderr.contains("already exists"));
 }
 
+#[test]
+fn test_add_same_hostname_for_ipv4_and_ipv6() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n127.0.0.1 app.local\n");
+    let path = hosts_file.path().to_str().unwrap();
+
+    let output = hostie_command_with_hosts_file(path)
+        .args(["add", "::1", "app.local"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+
+    // A second IPv6 address is still a duplicate
+    let output = hostie_command_with_hosts_file(path)
+        .args(["add", "fe80::1", "app.local"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("already exists"));
+
+    let output = hostie_command_with_hosts_file(path)
+        .args(["add", "--dual-stack", "api.local"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert!(content.contains("127.0.0.1 app.local\n::1 app.local\n"));
+    assert!(content.contains("127.0.0.1 api.local\n::1 api.local\n"));
+    assert!(!content.contains("fe80::1"));
+
+    // Both already exist
+    let output = hostie_command_with_hosts_file(path)
+        .args(["add", "--dual-stack", "api.local"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(!output.status.success());
+}
+
 #[test]
 fn test_remove_existing_entry() {
     let initial_content = "127.0.0.1 localhost\n192.168.1.100 test.local\n";
@@ -490,3 +538,2409 @@ fn test_whitespace_handling_in_entries() {
     assert!(stdout.contains("localhost"));
     assert!(stdout.contains("router.local"));
 }
+
+#[test]
+fn test_add_with_ttl_records_expiry() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .args(["add", "--ttl", "2h", "127.0.0.1", "demo.local"])
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(output.status.success());
+
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    let line = content
+        .lines()
+        .find(|line| line.starts_with("127.0.0.1 demo.local"))
+        .expect("Entry should be added");
+    assert!(line.contains("# hostie:expires="));
+}
+
+#[test]
+fn test_gc_removes_expired_entries() {
+    let initial_content = "127.0.0.1 localhost\n\
+                           127.0.0.1 old.local # hostie:expires=2020-01-01T00:00:00Z\n\
+                           127.0.0.1 new.local # hostie:expires=2999-01-01T00:00:00Z\n";
+    let hosts_file = create_test_hosts_file(initial_content);
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .arg("gc")
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("old.local"));
+
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert!(!content.contains("old.local"));
+    assert!(content.contains("new.local"));
+    assert!(content.contains("127.0.0.1 localhost"));
+}
+
+#[test]
+fn test_expired_entries_pruned_on_any_command() {
+    let initial_content = "# Header comment\n\
+                           127.0.0.1 localhost\n\
+                           127.0.0.1 old.local # hostie:expires=2020-01-01T00:00:00Z\n";
+    let hosts_file = create_test_hosts_file(initial_content);
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .arg("list")
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("old.local"));
+
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert_eq!(content, "# Header comment\n127.0.0.1 localhost\n");
+}
+
+#[test]
+fn test_remove_entry_with_ttl() {
+    let initial_content = "127.0.0.1 demo.local # hostie:expires=2999-01-01T00:00:00Z\n";
+    let hosts_file = create_test_hosts_file(initial_content);
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .args(["remove", "127.0.0.1", "demo.local"])
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(output.status.success());
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert!(!content.contains("demo.local"));
+}
+
+#[test]
+fn test_add_with_comment_and_tags() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args([
+            "add",
+            "--comment",
+            "owner: payments team",
+            "--tag",
+            "payments",
+            "10.1.2.3",
+            "pay.internal",
+        ])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert!(content.contains("10.1.2.3 pay.internal # owner: payments team hostie:tags=payments"));
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .arg("list")
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("owner: payments team"));
+    assert!(!stdout.contains("hostie:tags"));
+}
+
+#[test]
+fn test_add_rejects_comments_and_tags_that_would_not_read_back() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    for args in [
+        ["--tag", "pay ments"],
+        ["--tag", ""],
+        ["--tag", "a,b"],
+        ["--tag", "a#b"],
+        ["--comment", "x hostie:expires=2000-01-01T00:00:00Z"],
+        ["--comment", "two\nlines"],
+    ] {
+        let output = hostie_command_with_hosts_file(hosts_path)
+            .arg("add")
+            .args(args)
+            .args(["10.1.2.3", "pay.internal"])
+            .output()
+            .expect("Failed to execute hostie");
+        assert!(!output.status.success(), "{args:?}");
+        assert!(
+            String::from_utf8(output.stderr)
+                .unwrap()
+                .contains("invalid value"),
+            "{args:?}"
+        );
+    }
+
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n"
+    );
+}
+
+#[test]
+fn test_list_by_tag() {
+    let initial_content = "127.0.0.1 localhost\n\
+                           10.1.2.3 pay.internal # hostie:tags=payments\n\
+                           10.1.2.4 search.internal # hostie:tags=search\n";
+    let hosts_file = create_test_hosts_file(initial_content);
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .args(["list", "--tag", "payments"])
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("pay.internal"));
+    assert!(!stdout.contains("search.internal"));
+    assert!(!stdout.contains("localhost"));
+}
+
+#[test]
+fn test_remove_by_tag() {
+    let initial_content = "127.0.0.1 localhost\n\
+                           10.1.2.3 pay.internal # hostie:tags=payments\n\
+                           10.1.2.5 ledger.internal # owner: finance hostie:tags=search,payments\n\
+                           10.1.2.4 search.internal # hostie:tags=search\n";
+    let hosts_file = create_test_hosts_file(initial_content);
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .args(["remove", "--tag", "payments"])
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(output.status.success());
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert_eq!(
+        content,
+        "127.0.0.1 localhost\n10.1.2.4 search.internal # hostie:tags=search\n"
+    );
+}
+
+#[test]
+fn test_remove_entry_with_inline_comment() {
+    let initial_content = "127.0.0.1 localhost\n192.168.1.100 test.local # staging box\n";
+    let hosts_file = create_test_hosts_file(initial_content);
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .args(["remove", "192.168.1.100", "test.local"])
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(output.status.success());
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert_eq!(content, "127.0.0.1 localhost\n");
+}
+
+#[test]
+fn test_blocklist_add_imports_into_managed_block() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n127.0.0.1 tracker.com\n");
+    let blocklist = create_test_hosts_file(
+        "# Title: test list\n\
+         127.0.0.1 localhost\n\
+         0.0.0.0 0.0.0.0\n\
+         0.0.0.0 ads.example.com # ads\n\
+         0.0.0.0 tracker.com\n\
+         0.0.0.0 ads.example.com\n",
+    );
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["blocklist", "add", "ads"])
+        .arg(blocklist.path())
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("1 entries"));
+
+    // Hand-written entries win over the blocklist
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    let expected = format!(
+        "127.0.0.1 localhost\n\
+         127.0.0.1 tracker.com\n\
+         # BEGIN hostie blocklist:ads\n\
+         # source: {}\n\
+         0.0.0.0 ads.example.com\n\
+         # END hostie blocklist:ads\n",
+        fs::canonicalize(blocklist.path()).unwrap().display()
+    );
+    assert_eq!(content, expected);
+
+    // Blocklisted entries are summarised, not listed
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .arg("list")
+        .output()
+        .expect("Failed to execute hostie");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("ads.example.com"));
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["blocklist", "list"])
+        .output()
+        .expect("Failed to execute hostie");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("ads"));
+    assert!(stdout.contains("1 entries"));
+}
+
+#[test]
+fn test_blocklist_update_and_allowlist() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let blocklist = create_test_hosts_file("0.0.0.0 ads.example.com\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["blocklist", "add", "ads"])
+        .arg(blocklist.path())
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+
+    // Allowing a domain unblocks it immediately
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["blocklist", "allow", "ads.example.com"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert!(!content.contains("0.0.0.0 ads.example.com"));
+
+    // ...and keeps it unblocked when the list is refreshed
+    fs::write(
+        blocklist.path(),
+        "0.0.0.0 ads.example.com\n0.0.0.0 more-ads.example.com\n",
+    )
+    .unwrap();
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["blocklist", "update"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert!(!content.contains("0.0.0.0 ads.example.com"));
+    assert!(content.contains("0.0.0.0 more-ads.example.com"));
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["blocklist", "remove", "ads"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert!(!content.contains("blocklist:ads"));
+    assert!(!content.contains("more-ads.example.com"));
+    assert!(content.contains("127.0.0.1 localhost"));
+}
+
+#[test]
+fn test_blocklists_share_no_domains() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let first = create_test_hosts_file("0.0.0.0 a.com\n0.0.0.0 b.com\n");
+    let second = create_test_hosts_file("0.0.0.0 a.com\n0.0.0.0 c.com\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    for (name, list) in [("first", &first), ("second", &second)] {
+        let output = hostie_command_with_hosts_file(hosts_path)
+            .args(["blocklist", "add", name])
+            .arg(list.path())
+            .output()
+            .expect("Failed to execute hostie");
+        assert!(output.status.success());
+    }
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["blocklist", "update"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert_eq!(content.matches("0.0.0.0 a.com").count(), 1);
+    assert!(content.contains("0.0.0.0 b.com"));
+    assert!(content.contains("0.0.0.0 c.com"));
+
+    // Once the list blocking it is gone, an update of the other blocks it
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["blocklist", "remove", "first"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["blocklist", "update"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert!(content.contains("0.0.0.0 a.com"));
+    assert!(!content.contains("b.com"));
+}
+
+#[test]
+fn test_add_takes_a_hostname_off_blocklists() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let blocklist = create_test_hosts_file("0.0.0.0 ads.example.com\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["blocklist", "add", "ads"])
+        .arg(blocklist.path())
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["add", "10.0.0.5", "ads.example.com"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("removed from blocklists"));
+
+    // The hand-written entry also survives an update of the list
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["blocklist", "update"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert!(content.contains("10.0.0.5 ads.example.com"));
+    assert!(!content.contains("0.0.0.0 ads.example.com"));
+}
+
+#[test]
+fn test_watch_reports_external_changes() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let hosts_path = dir.path().join("hosts");
+    fs::write(&hosts_path, "127.0.0.1 localhost\n10.0.0.1 old.corp\n").unwrap();
+
+    let mut child = hostie_command_with_hosts_file(hosts_path.to_str().unwrap())
+        .arg("watch")
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute hostie");
+
+    let (tx, rx) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if tx.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let first = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert!(first.contains("Watching"));
+
+    // Replace the file the way VPN clients do: write elsewhere and rename
+    let replacement = dir.path().join("hosts.new");
+    fs::write(&replacement, "127.0.0.1 localhost\n10.0.0.2 new.corp\n").unwrap();
+    fs::rename(&replacement, &hosts_path).unwrap();
+
+    let mut output = Vec::new();
+    while let Ok(line) = rx.recv_timeout(Duration::from_secs(5)) {
+        output.push(line);
+        if output.len() == 2 {
+            break;
+        }
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert_eq!(output.len(), 2, "Unexpected output: {output:?}");
+    assert!(output[0].contains("- ") && output[0].contains("10.0.0.1 old.corp"));
+    assert!(output[1].contains("+ ") && output[1].contains("10.0.0.2 new.corp"));
+}
+
+#[test]
+fn test_enforce_once_restores_manifest_entries() {
+    let hosts_file =
+        create_test_hosts_file("127.0.0.1 localhost\n10.9.9.9 api.local # added by vpn\n");
+    let manifest = create_test_hosts_file("127.0.0.1 api.local\n127.0.0.1 web.local\n");
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .args(["enforce", "--once", "--manifest"])
+        .arg(manifest.path())
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("restored"));
+    assert!(stdout.contains("removed conflicting"));
+
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert_eq!(
+        content,
+        "127.0.0.1 localhost\n\
+         # BEGIN hostie enforce\n\
+         127.0.0.1 api.local\n\
+         127.0.0.1 web.local\n\
+         # END hostie enforce\n"
+    );
+
+    // Nothing to correct the second time around
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .args(["enforce", "--once", "--manifest"])
+        .arg(manifest.path())
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("restored"));
+    assert_eq!(fs::read_to_string(hosts_file.path()).unwrap(), content);
+}
+
+#[test]
+fn test_enforce_keeps_other_hostnames_of_a_conflicting_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let hosts_path = dir.path().join("hosts");
+    fs::write(
+        &hosts_path,
+        "127.0.0.1 localhost\n10.9.9.9 api.local db.local # added by vpn\n",
+    )
+    .unwrap();
+    let manifest = create_test_hosts_file("127.0.0.1 api.local\n");
+
+    let output = hostie_command_with_hosts_file(hosts_path.to_str().unwrap())
+        .args(["enforce", "--once", "--manifest"])
+        .arg(manifest.path())
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+
+    let content = fs::read_to_string(&hosts_path).unwrap();
+    assert_eq!(
+        content,
+        "127.0.0.1 localhost\n\
+         10.9.9.9 db.local # added by vpn\n\
+         # BEGIN hostie enforce\n\
+         127.0.0.1 api.local\n\
+         # END hostie enforce\n"
+    );
+
+    // Neither the lock nor the file written to replace the hosts file stay
+    let names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["hosts"]);
+}
+
+#[test]
+fn test_concurrent_adds_keep_every_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let hosts_path = dir.path().join("hosts");
+    fs::write(&hosts_path, "127.0.0.1 localhost\n").unwrap();
+
+    let children: Vec<_> = (0..30)
+        .map(|i| {
+            hostie_command_with_hosts_file(hosts_path.to_str().unwrap())
+                .args(["add", "127.0.0.1", &format!("app{i}.local")])
+                .stdout(std::process::Stdio::null())
+                .spawn()
+                .expect("Failed to execute hostie")
+        })
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    let content = fs::read_to_string(&hosts_path).unwrap();
+    for i in 0..30 {
+        assert!(
+            content.contains(&format!("127.0.0.1 app{i}.local\n")),
+            "app{i}.local is missing from:\n{content}"
+        );
+    }
+    let names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["hosts"]);
+}
+
+#[test]
+fn test_enforce_reapplies_entries_after_external_change() {
+    use std::process::Stdio;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let dir = tempfile::tempdir().unwrap();
+    let hosts_path = dir.path().join("hosts");
+    fs::write(&hosts_path, "127.0.0.1 localhost\n").unwrap();
+    let manifest = create_test_hosts_file("127.0.0.1 api.local\n");
+
+    let mut child = hostie_command_with_hosts_file(hosts_path.to_str().unwrap())
+        .args(["enforce", "--manifest"])
+        .arg(manifest.path())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to execute hostie");
+
+    let wait_for_entry = || {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if fs::read_to_string(&hosts_path)
+                .unwrap_or_default()
+                .contains("127.0.0.1 api.local")
+            {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        false
+    };
+
+    assert!(wait_for_entry(), "Entry should be applied on start");
+
+    // Simulate a VPN client rewriting the file from scratch
+    fs::write(&hosts_path, "127.0.0.1 localhost\n10.0.0.1 corp.internal\n").unwrap();
+    let restored = wait_for_entry();
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert!(restored, "Entry should be re-applied after removal");
+    let content = fs::read_to_string(&hosts_path).unwrap();
+    assert!(content.contains("10.0.0.1 corp.internal"));
+}
+
+#[test]
+fn test_compose_up_and_down() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+    let compose_file = create_test_hosts_file(
+        r#"
+name: shop
+services:
+  api:
+    image: example/api
+    hostname: api.shop.test
+    extra_hosts:
+      - "payments.internal:10.0.0.5"
+      - "host.docker.internal:host-gateway"
+  web:
+    image: example/web
+    labels:
+      traefik.http.routers.web.rule: "Host(`shop.test`) || Host(`www.shop.test`)"
+  admin:
+    image: example/admin
+    environment:
+      - VIRTUAL_HOST=admin.shop.test,*.admin.shop.test
+"#,
+    );
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["compose", "up", "-f"])
+        .arg(compose_file.path())
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert_eq!(
+        content,
+        "127.0.0.1 localhost\n\
+         # BEGIN hostie compose:shop\n\
+         127.0.0.1 api\n\
+         127.0.0.1 api.shop.test\n\
+         127.0.0.1 payments.internal\n\
+         127.0.0.1 web\n\
+         127.0.0.1 shop.test\n\
+         127.0.0.1 www.shop.test\n\
+         127.0.0.1 admin\n\
+         127.0.0.1 admin.shop.test\n\
+         # END hostie compose:shop\n"
+    );
+
+    // Running it again replaces the block rather than duplicating it
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["compose", "up", "-f"])
+        .arg(compose_file.path())
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(hosts_file.path()).unwrap(), content);
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["compose", "down", "-f"])
+        .arg(compose_file.path())
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n"
+    );
+}
+
+/// Stand-in for the Docker Engine API: serves `containers` from
+/// `/containers/json` and sends one event for every message on `events`.
+#[cfg(unix)]
+fn serve_engine_api(
+    socket: &std::path::Path,
+    containers: std::sync::Arc<std::sync::Mutex<String>>,
+    events: std::sync::mpsc::Receiver<()>,
+) {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    let listener = UnixListener::bind(socket).unwrap();
+    let events = Arc::new(Mutex::new(events));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let containers = containers.clone();
+            let events = events.clone();
+            thread::spawn(move || {
+                let mut request = String::new();
+                BufReader::new(&stream).read_line(&mut request).unwrap();
+
+                if request.starts_with("GET /containers/json ") {
+                    let body = containers.lock().unwrap().clone();
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                        body.len()
+                    )
+                    .unwrap();
+                } else if request.starts_with("GET /events") {
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"
+                    )
+                    .unwrap();
+                    while events.lock().unwrap().recv().is_ok() {
+                        let event = r#"{"Type":"container","Action":"start"}"#;
+                        write!(stream, "{:x}\r\n{event}\r\n", event.len()).unwrap();
+                    }
+                } else {
+                    write!(stream, "HTTP/1.1 404 Not Found\r\n\r\n").unwrap();
+                }
+            });
+        }
+    });
+}
+
+#[cfg(unix)]
+const ENGINE_CONTAINERS: &str = r#"[
+  {
+    "Id": "3f4e8a9b2c1d0e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1d2e3f4a5b6c7d8e9f",
+    "Names": ["/shop-web-1"],
+    "NetworkSettings": {
+      "Networks": {
+        "shop_default": {
+          "IPAddress": "172.18.0.3",
+          "Aliases": null,
+          "DNSNames": ["shop-web-1", "web", "3f4e", "3f4e8a9b2c1d"]
+        }
+      }
+    }
+  },
+  {
+    "Id": "9a8b7c6d5e4f",
+    "Names": ["/stopped-network"],
+    "NetworkSettings": { "Networks": { "none": { "IPAddress": "" } } }
+  }
+]"#;
+
+#[cfg(unix)]
+#[test]
+fn test_containers_sync() {
+    use std::sync::{Arc, Mutex, mpsc};
+
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("docker.sock");
+    let (_events, events_rx) = mpsc::channel();
+    serve_engine_api(
+        &socket,
+        Arc::new(Mutex::new(ENGINE_CONTAINERS.to_string())),
+        events_rx,
+    );
+
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .args(["containers", "sync", "--socket"])
+        .arg(&socket)
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(output.status.success());
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert_eq!(
+        content,
+        "127.0.0.1 localhost\n\
+         # BEGIN hostie containers\n\
+         172.18.0.3 shop-web-1 web 3f4e\n\
+         # END hostie containers\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_containers_sync_follow() {
+    use std::process::Stdio;
+    use std::sync::{Arc, Mutex, mpsc};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("docker.sock");
+    let containers = Arc::new(Mutex::new("[]".to_string()));
+    let (events, events_rx) = mpsc::channel();
+    serve_engine_api(&socket, containers.clone(), events_rx);
+
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let mut child = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .args(["containers", "sync", "--follow", "--socket"])
+        .arg(&socket)
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to execute hostie");
+
+    // A container starts after hostie has subscribed to events
+    thread::sleep(Duration::from_millis(500));
+    *containers.lock().unwrap() = ENGINE_CONTAINERS.to_string();
+    events.send(()).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut synced = false;
+    while !synced && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(50));
+        synced = fs::read_to_string(hosts_file.path())
+            .unwrap()
+            .contains("172.18.0.3 shop-web-1 web");
+    }
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(synced, "Containers should be synced after an event");
+}
+
+#[test]
+fn test_k8s_import_from_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifests = dir.path().join("shop");
+    fs::create_dir_all(manifests.join("routes")).unwrap();
+    fs::write(
+        manifests.join("ingress.yaml"),
+        r#"
+apiVersion: networking.k8s.io/v1
+kind: Ingress
+metadata:
+  name: shop
+spec:
+  rules:
+    - host: shop.test
+    - host: "*.shop.test"
+    - host: api.shop.test
+---
+apiVersion: v1
+kind: Service
+metadata:
+  name: shop
+"#,
+    )
+    .unwrap();
+    fs::write(
+        manifests.join("routes/route.yml"),
+        r#"
+apiVersion: gateway.networking.k8s.io/v1
+kind: HTTPRoute
+metadata:
+  name: admin
+spec:
+  hostnames:
+    - admin.shop.test
+    - shop.test
+"#,
+    )
+    .unwrap();
+    fs::write(manifests.join("README.md"), "not a manifest").unwrap();
+
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["k8s", "import", "--ip", "172.18.0.2"])
+        .arg(&manifests)
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("*.shop.test"));
+
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert_eq!(
+        content,
+        "127.0.0.1 localhost\n\
+         # BEGIN hostie k8s:shop\n\
+         172.18.0.2 shop.test\n\
+         172.18.0.2 api.shop.test\n\
+         172.18.0.2 admin.shop.test\n\
+         # END hostie k8s:shop\n"
+    );
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["k8s", "remove", "shop"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_k8s_import_survives_symlink_loops() {
+    use std::os::unix::fs::symlink;
+
+    let dir = tempfile::tempdir().unwrap();
+    let manifests = dir.path().join("shop");
+    fs::create_dir(&manifests).unwrap();
+    fs::write(
+        manifests.join("ingress.yaml"),
+        "kind: Ingress\nspec:\n  rules:\n    - host: shop.test\n",
+    )
+    .unwrap();
+    symlink("..", manifests.join("up")).unwrap();
+    symlink(".", manifests.join("self")).unwrap();
+
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .args(["k8s", "import", "--ip", "172.18.0.2"])
+        .arg(&manifests)
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n\
+         # BEGIN hostie k8s:shop\n\
+         172.18.0.2 shop.test\n\
+         # END hostie k8s:shop\n"
+    );
+}
+
+#[test]
+fn test_scan_web_config_proposes_and_applies_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("shop.conf"),
+        "server {\n    listen 80;\n    server_name shop.test\n                www.shop.test;  # main site\n}\n\
+         server {\n    listen 80 default_server;\n    server_name _ ~^(?<sub>.+)\\.wild\\.test$ .dot.test;\n}\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("blog-vhost.conf"),
+        "<VirtualHost *:80>\n    ServerName https://blog.test:443\n    serveralias news.test shop.test\n</VirtualHost>\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("Caddyfile"),
+        "{\n    email dev@example.com\n}\n\n(common) {\n    encode gzip\n}\n\n\
+         api.test, admin.test:8443 {\n    import common\n    reverse_proxy localhost:3000\n}\n\n\
+         :8080 {\n    respond \"ok\"\n}\n",
+    )
+    .unwrap();
+
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n127.0.0.1 admin.test\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .arg("scan-web-config")
+        .arg(dir.path())
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("--apply"));
+    assert!(stdout.contains("api.test"));
+    assert!(!stdout.contains("admin.test"));
+    assert!(!stdout.contains("wild.test"));
+    assert!(!stdout.contains("dot.test"));
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n127.0.0.1 admin.test\n",
+        "Nothing should be written without --apply"
+    );
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["scan-web-config", "--apply"])
+        .arg(dir.path())
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n\
+         127.0.0.1 admin.test\n\
+         127.0.0.1 api.test\n\
+         127.0.0.1 blog.test\n\
+         127.0.0.1 news.test\n\
+         127.0.0.1 shop.test\n\
+         127.0.0.1 www.shop.test\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_scan_web_config_survives_symlink_loops() {
+    let dir = tempfile::tempdir().unwrap();
+    let sites = dir.path().join("sites-enabled");
+    fs::create_dir(&sites).unwrap();
+    fs::write(
+        sites.join("shop.conf"),
+        "server {\n    server_name shop.test;\n}\n",
+    )
+    .unwrap();
+    std::os::unix::fs::symlink(".", sites.join("self")).unwrap();
+    std::os::unix::fs::symlink("..", sites.join("parent")).unwrap();
+
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .arg("scan-web-config")
+        .arg(dir.path())
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.matches("shop.test").count(), 1);
+}
+
+/// Sends a DNS query and returns the response's rcode and answer records as
+/// `(type, rdata)` pairs.
+fn dns_query(server: &str, name: &str, qtype: u16) -> (u8, Vec<(u16, Vec<u8>)>) {
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in name.split('.') {
+        query.push(label.len() as u8);
+        query.extend(label.as_bytes());
+    }
+    query.push(0);
+    query.extend(qtype.to_be_bytes());
+    query.extend(1u16.to_be_bytes());
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    socket.send_to(&query, server).unwrap();
+    let mut buf = [0; 512];
+    let len = socket.recv(&mut buf).unwrap();
+    let response = &buf[..len];
+
+    assert_eq!(&response[..2], &[0x12, 0x34]);
+    let rcode = response[3] & 0x0f;
+    let ancount = u16::from_be_bytes([response[6], response[7]]);
+
+    // Answers follow the echoed question and use a 2 byte name pointer
+    let mut pos = query.len();
+    let mut answers = Vec::new();
+    for _ in 0..ancount {
+        let rtype = u16::from_be_bytes([response[pos + 2], response[pos + 3]]);
+        let rdlength = u16::from_be_bytes([response[pos + 10], response[pos + 11]]) as usize;
+        answers.push((rtype, response[pos + 12..pos + 12 + rdlength].to_vec()));
+        pos += 12 + rdlength;
+    }
+    (rcode, answers)
+}
+
+#[test]
+fn test_serve_dns_answers_from_hosts_file() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let hosts_file = create_test_hosts_file(
+        "127.0.0.1 localhost\n\
+         10.1.2.3 api.test\n\
+         ::1 api.test\n\
+         10.9.9.9 *.preview.test\n",
+    );
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let mut child = hostie_command_with_hosts_file(hosts_path)
+        .args(["serve-dns", "--listen", "127.0.0.1:0"])
+        .args(["--wildcard", "*.myapp.test=127.0.0.2"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute hostie");
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    assert!(line.contains("Listening"), "Unexpected output: {line}");
+    let server = line.split_whitespace().nth(5).unwrap().to_string();
+
+    let (rcode, answers) = dns_query(&server, "API.test", 1);
+    assert_eq!(rcode, 0);
+    assert_eq!(answers, vec![(1, vec![10, 1, 2, 3])]);
+
+    let (rcode, answers) = dns_query(&server, "api.test", 28);
+    assert_eq!(rcode, 0);
+    let mut loopback = vec![0; 15];
+    loopback.push(1);
+    assert_eq!(answers, vec![(28, loopback)]);
+
+    let (_, answers) = dns_query(&server, "a.b.myapp.test", 1);
+    assert_eq!(answers, vec![(1, vec![127, 0, 0, 2])]);
+    let (_, answers) = dns_query(&server, "pr-42.preview.test", 1);
+    assert_eq!(answers, vec![(1, vec![10, 9, 9, 9])]);
+
+    let (rcode, answers) = dns_query(&server, "3.2.1.10.in-addr.arpa", 12);
+    assert_eq!(rcode, 0);
+    assert_eq!(answers, vec![(12, b"\x03api\x04test\x00".to_vec())]);
+
+    // Without --upstream, names that aren't ours are refused
+    let (rcode, answers) = dns_query(&server, "example.com", 1);
+    assert_eq!(rcode, 5);
+    assert!(answers.is_empty());
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn test_serve_dns_forwards_to_upstream() {
+    use std::io::{BufRead, BufReader};
+    use std::net::UdpSocket;
+    use std::process::Stdio;
+    use std::thread;
+
+    // Answers NXDOMAIN for everything, echoing the message ID it was given
+    let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let upstream_addr = upstream.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        while let Ok((len, from)) = upstream.recv_from(&mut buf) {
+            buf[2] |= 0x80;
+            buf[3] = 0x03;
+            let _ = upstream.send_to(&buf[..len], from);
+        }
+    });
+
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let mut child = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .args(["serve-dns", "--listen", "127.0.0.1:0", "--upstream"])
+        .arg(&upstream_addr)
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute hostie");
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let server = line.split_whitespace().nth(5).unwrap().to_string();
+
+    // Clients reusing an ID still get their own answer back
+    for name in ["example.com", "example.org"] {
+        let (rcode, answers) = dns_query(&server, name, 1);
+        assert_eq!(rcode, 3);
+        assert!(answers.is_empty());
+    }
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn test_add_hostname_only_with_port() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["add", "myapp.test", "--port", "3000"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("port 3000")
+    );
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n127.0.0.1 myapp.test # hostie:port=3000\n"
+    );
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .arg("list")
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("(port 3000)")
+    );
+}
+
+/// Starts a stand-in for an app, echoing the request head it received, and
+/// returns its port.
+fn spawn_echo_app() -> u16 {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    let app = TcpListener::bind("127.0.0.1:0").unwrap();
+    let app_port = app.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in app.incoming() {
+            let mut stream = stream.unwrap();
+            let mut head = String::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{head}",
+                head.len()
+            )
+            .unwrap();
+        }
+    });
+    app_port
+}
+
+#[test]
+fn test_proxy_routes_by_host_header() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::process::Stdio;
+
+    let app_port = spawn_echo_app();
+
+    let hosts_file = create_test_hosts_file(&format!(
+        "127.0.0.1 localhost\n127.0.0.1 myapp.test # hostie:port={app_port}\n"
+    ));
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let mut child = hostie_command_with_hosts_file(hosts_path)
+        .args(["proxy", "--listen", "127.0.0.1:0", "--no-tls"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute hostie");
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    assert!(line.contains("Proxying"), "Unexpected output: {line}");
+    let proxy = line.split_whitespace().nth(4).unwrap().to_string();
+
+    let request = |host: &str| {
+        let mut stream = TcpStream::connect(&proxy).unwrap();
+        write!(
+            stream,
+            "GET /hello HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let response = request("myapp.test:80");
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.contains("GET /hello HTTP/1.1"));
+    assert!(response.contains("X-Forwarded-Host: myapp.test:80"));
+
+    let response = request("other.test");
+    assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn test_proxy_serves_https_and_closes_after_each_request() {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, ServerName};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::process::Stdio;
+    use std::sync::Arc;
+
+    let app_port = spawn_echo_app();
+    let data_dir = tempfile::tempdir().unwrap();
+    let hosts_file = create_test_hosts_file(&format!(
+        "127.0.0.1 localhost\n127.0.0.1 myapp.test # hostie:port={app_port}\n"
+    ));
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    // The CA has to exist before the client can trust it
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .env("XDG_DATA_HOME", data_dir.path())
+        .args(["cert", "ca.test"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+
+    let mut child = hostie_command_with_hosts_file(hosts_path)
+        .env("XDG_DATA_HOME", data_dir.path())
+        .args([
+            "proxy",
+            "--listen",
+            "127.0.0.1:0",
+            "--listen-tls",
+            "127.0.0.1:0",
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute hostie");
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut addresses = Vec::new();
+    for _ in 0..2 {
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        addresses.push(line.split_whitespace().nth(4).unwrap().to_string());
+    }
+    let (http, https) = (&addresses[0], &addresses[1]);
+
+    // A keep-alive client can't reach another app on the same connection
+    let mut stream = TcpStream::connect(http).unwrap();
+    write!(
+        stream,
+        "GET /one HTTP/1.1\r\nHost: myapp.test\r\nConnection: keep-alive\r\n\r\n\
+         GET /two HTTP/1.1\r\nHost: other.test\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert_eq!(response.matches("HTTP/1.1 ").count(), 1, "{response}");
+    assert!(response.contains("GET /one HTTP/1.1"));
+    assert!(response.contains("Connection: close"));
+    assert!(!response.contains("keep-alive"));
+    assert!(!response.contains("/two"));
+
+    let ca = CertificateDer::from_pem_file(data_dir.path().join("hostie/ca.pem")).unwrap();
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(ca).unwrap();
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    let connection = rustls::ClientConnection::new(
+        Arc::new(config),
+        ServerName::try_from("myapp.test").unwrap(),
+    )
+    .unwrap();
+    let mut tls = rustls::StreamOwned::new(connection, TcpStream::connect(https).unwrap());
+    write!(tls, "GET /secure HTTP/1.1\r\nHost: myapp.test\r\n\r\n").unwrap();
+    let mut response = String::new();
+    tls.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.contains("GET /secure HTTP/1.1"));
+    assert!(response.contains("X-Forwarded-Proto: https"));
+    assert!(data_dir.path().join("hostie/certs/myapp.test.pem").exists());
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn test_cert_issues_certificates_from_one_ca() {
+    use x509_parser::extensions::GeneralName;
+    use x509_parser::pem::parse_x509_pem;
+
+    let data_dir = tempfile::tempdir().unwrap();
+    let hosts_file =
+        create_test_hosts_file("127.0.0.1 localhost\n127.0.0.1 myapp.test www.myapp.test\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .env("XDG_DATA_HOME", data_dir.path())
+        .args(["cert", "myapp.test"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("Created a local CA")
+    );
+
+    let hostie_dir = data_dir.path().join("hostie");
+    let ca_pem = fs::read(hostie_dir.join("ca.pem")).unwrap();
+    assert!(hostie_dir.join("certs/myapp.test-key.pem").exists());
+
+    // A second certificate reuses the CA
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .env("XDG_DATA_HOME", data_dir.path())
+        .args(["add", "api.test", "--tls"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Issued a certificate"));
+    assert!(!stdout.contains("Created a local CA"));
+    assert_eq!(fs::read(hostie_dir.join("ca.pem")).unwrap(), ca_pem);
+
+    let (_, ca) = parse_x509_pem(&ca_pem).unwrap();
+    let ca = ca.parse_x509().unwrap();
+    assert!(ca.is_ca());
+
+    for (file, names) in [
+        ("myapp.test.pem", vec!["myapp.test", "www.myapp.test"]),
+        ("api.test.pem", vec!["api.test"]),
+    ] {
+        let pem = fs::read(hostie_dir.join("certs").join(file)).unwrap();
+        let (_, pem) = parse_x509_pem(&pem).unwrap();
+        let cert = pem.parse_x509().unwrap();
+        assert_eq!(cert.issuer(), ca.subject());
+        cert.verify_signature(Some(ca.public_key())).unwrap();
+
+        let sans: Vec<&str> = cert
+            .subject_alternative_name()
+            .unwrap()
+            .unwrap()
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(name) => Some(*name),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sans, names);
+    }
+}
+
+#[test]
+fn test_add_with_tls_writes_nothing_if_issuing_fails() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .env("XDG_DATA_HOME", data_dir.path())
+        .args(["add", "bad!name.test", "--tls"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(!output.status.success());
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n"
+    );
+}
+
+#[test]
+fn test_user_aliases() {
+    let home = tempfile::tempdir().unwrap();
+    let aliases_path = home.path().join(".hosts");
+    let user_command = |args: &[&str]| {
+        let mut command = hostie_command();
+        command
+            .env("HOME", home.path())
+            .env_remove("HOSTALIASES")
+            .env("HOSTIE_HOSTS_FILE", home.path().join("unused-hosts"))
+            .arg("--user")
+            .args(args);
+        command.output().expect("Failed to execute hostie")
+    };
+
+    let output = user_command(&["add-alias", "db", "db.staging.internal"]);
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(&aliases_path).unwrap(),
+        "db db.staging.internal\n"
+    );
+
+    assert!(
+        !user_command(&["add-alias", "db", "other.internal"])
+            .status
+            .success()
+    );
+    assert!(
+        !user_command(&["add-alias", "db.x", "other.internal"])
+            .status
+            .success()
+    );
+
+    let output = user_command(&["list"]);
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("db.staging.internal")
+    );
+
+    // Aliases map names to names, there's nothing to do with an address
+    let output = user_command(&["add", "10.0.0.1", "db"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("--user"));
+
+    let output = user_command(&["env"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        format!("export HOSTALIASES='{}'", aliases_path.display())
+    );
+
+    assert!(user_command(&["remove-alias", "db"]).status.success());
+    assert_eq!(fs::read_to_string(&aliases_path).unwrap(), "");
+    assert!(!home.path().join("unused-hosts").exists());
+}
+
+#[test]
+fn test_store_flag_targets_the_nss_store() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+    let store_path = data_dir.path().join("hostie/hosts");
+
+    for args in [
+        ["--store", "add", "myapp.test"],
+        ["--store", "add", "*.preview.test"],
+    ] {
+        let output = hostie_command_with_hosts_file(hosts_path)
+            .env("XDG_DATA_HOME", data_dir.path())
+            .args(args)
+            .output()
+            .expect("Failed to execute hostie");
+        assert!(output.status.success());
+    }
+
+    assert_eq!(
+        fs::read_to_string(&store_path).unwrap(),
+        "127.0.0.1 myapp.test\n127.0.0.1 *.preview.test\n"
+    );
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n"
+    );
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .env("XDG_DATA_HOME", data_dir.path())
+        .args(["remove", "--store", "127.0.0.1", "myapp.test"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(&store_path).unwrap(),
+        "127.0.0.1 *.preview.test\n"
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_sandbox_sees_extra_entries() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+    let real_hosts = fs::read_to_string("/etc/hosts").unwrap();
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["sandbox", "--add", "10.9.8.7=api.prod.example.com", "--"])
+        .args(["sh", "-c", "cat /etc/hosts; exit 3"])
+        .output()
+        .expect("Failed to execute hostie");
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    if stderr.contains("Could not start") {
+        eprintln!("Skipping, user namespaces are unavailable: {stderr}");
+        return;
+    }
+
+    assert_eq!(output.status.code(), Some(3), "{stderr}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "127.0.0.1 localhost\n10.9.8.7 api.prod.example.com\n"
+    );
+    assert_eq!(fs::read_to_string("/etc/hosts").unwrap(), real_hosts);
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n"
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_sandbox_entries_replace_existing_mappings() {
+    let hosts_file = create_test_hosts_file(
+        "127.0.0.1 localhost api.prod.example.com\n::1 api.prod.example.com\n",
+    );
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["sandbox", "--add", "10.9.8.7=api.prod.example.com", "--"])
+        .args(["cat", "/etc/hosts"])
+        .output()
+        .expect("Failed to execute hostie");
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    if stderr.contains("Could not start") {
+        eprintln!("Skipping, user namespaces are unavailable: {stderr}");
+        return;
+    }
+
+    assert!(output.status.success(), "{stderr}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "127.0.0.1 localhost\n::1 api.prod.example.com\n10.9.8.7 api.prod.example.com\n"
+    );
+}
+
+#[test]
+fn test_run_removes_its_entries_when_the_command_exits() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .args(["run", "--host", "api.local", "--host", "db.local=10.0.0.5"])
+        .args(["--", "sh", "-c", "cat \"$HOSTIE_HOSTS_FILE\"; exit 4"])
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert_eq!(output.status.code(), Some(4));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("127.0.0.1 api.local # hostie:run="),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("10.0.0.5 db.local # hostie:run="),
+        "{stdout}"
+    );
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_run_cleans_up_on_sigterm() {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let mut child = hostie_command_with_hosts_file(hosts_path)
+        .args(["run", "--host", "api.local", "--", "sleep", "30"])
+        .spawn()
+        .expect("Failed to execute hostie");
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !fs::read_to_string(hosts_file.path())
+        .unwrap()
+        .contains("api.local")
+    {
+        assert!(Instant::now() < deadline, "Entry was never added");
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let killed = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+
+    // The signal is forwarded, so sleep dies of it too
+    let status = child.wait().unwrap();
+    assert_eq!(status.code(), Some(128 + 15));
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_leftover_run_entries_are_pruned() {
+    // A process that has certainly exited stands in for a crashed hostie
+    let mut gone = Command::new("true").spawn().unwrap();
+    let pid = gone.id();
+    gone.wait().unwrap();
+
+    let hosts_file = create_test_hosts_file(&format!(
+        "127.0.0.1 localhost\n\
+         127.0.0.1 stale.local # hostie:run={pid}\n\
+         127.0.0.1 live.local # hostie:run={}\n",
+        std::process::id()
+    ));
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .arg("gc")
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("stale.local")
+    );
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        format!(
+            "127.0.0.1 localhost\n127.0.0.1 live.local # hostie:run={}\n",
+            std::process::id()
+        )
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_run_entries_are_told_apart_by_start_time_and_namespace() {
+    use hostie::owner::Owner;
+
+    let current = Owner::current();
+    assert!(current.started.is_some() && current.namespace.is_some());
+
+    let mut gone = Command::new("true").spawn().unwrap();
+    let gone_pid = gone.id();
+    gone.wait().unwrap();
+
+    // This process, recorded with another start time, is one that got the ID
+    // of a process that is gone. A process of another PID namespace can't be
+    // looked up, so it stays.
+    let kept = format!(
+        "127.0.0.1 localhost\n\
+         127.0.0.1 live.local # hostie:run={current}\n\
+         127.0.0.1 container.local # hostie:run=1/{gone_pid}@5\n"
+    );
+    let hosts_file = create_test_hosts_file(&format!(
+        "{kept}127.0.0.1 reused.local # hostie:run={}@1\n",
+        current.pid
+    ));
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .arg("gc")
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(hosts_file.path()).unwrap(), kept);
+}
+
+#[test]
+fn test_hosts_guard_removes_its_entries() {
+    use hostie::guard::HostsGuard;
+
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+
+    {
+        let _guard = HostsGuard::add_to(
+            hosts_file.path(),
+            &[("127.0.0.1", "svc.test"), ("10.0.0.5", "db.test")],
+        )
+        .unwrap();
+
+        let content = fs::read_to_string(hosts_file.path()).unwrap();
+        assert!(content.contains("127.0.0.1 svc.test # hostie:run="));
+        assert!(content.contains("10.0.0.5 db.test # hostie:run="));
+
+        // A hostname that's taken fails without adding anything
+        let err = HostsGuard::add_to(
+            hosts_file.path(),
+            &[("127.0.0.1", "other.test"), ("127.0.0.1", "svc.test")],
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(
+            !fs::read_to_string(hosts_file.path())
+                .unwrap()
+                .contains("other.test")
+        );
+    }
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n"
+    );
+
+    // Also when a test fails while holding the guard
+    let path = hosts_file.path().to_path_buf();
+    let result = std::panic::catch_unwind(|| {
+        let _guard = HostsGuard::add_to(&path, &[("127.0.0.1", "svc.test")]).unwrap();
+        panic!("test failure");
+    });
+    assert!(result.is_err());
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_hosts_guard_waits_for_the_file_lock() {
+    use hostie::guard::HostsGuard;
+    use std::thread;
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hosts");
+    fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+    // Held the way another process, e.g. `hostie enforce`, would hold it
+    let lock = hostie::file::lock(&path).unwrap();
+    let adding = {
+        let path = path.clone();
+        thread::spawn(move || HostsGuard::add_to(&path, &[("127.0.0.1", "svc.test")]).unwrap())
+    };
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "127.0.0.1 localhost\n");
+
+    drop(lock);
+    let guard = adding.join().unwrap();
+    assert!(
+        fs::read_to_string(&path)
+            .unwrap()
+            .contains("127.0.0.1 svc.test # hostie:run=")
+    );
+
+    drop(guard);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "127.0.0.1 localhost\n");
+    let names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["hosts"]);
+}
+
+#[test]
+fn test_hosts_guards_and_commands_keep_each_others_entries() {
+    use hostie::guard::HostsGuard;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hosts");
+    fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+    let children: Vec<_> = (0..20)
+        .map(|i| {
+            hostie_command_with_hosts_file(path.to_str().unwrap())
+                .args(["add", "127.0.0.1", &format!("app{i}.local")])
+                .stdout(std::process::Stdio::null())
+                .spawn()
+                .expect("Failed to execute hostie")
+        })
+        .collect();
+    for i in 0..20 {
+        let guard = HostsGuard::add_to(&path, &[("127.0.0.1", &format!("svc{i}.test"))]).unwrap();
+        if i % 2 == 0 {
+            drop(guard);
+        } else {
+            std::mem::forget(guard);
+        }
+    }
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    let content = fs::read_to_string(&path).unwrap();
+    for i in 0..20 {
+        assert!(
+            content.contains(&format!("127.0.0.1 app{i}.local\n")),
+            "{content}"
+        );
+        // Only the guards that were dropped removed their entries
+        assert_eq!(
+            content.contains(&format!("127.0.0.1 svc{i}.test #")),
+            i % 2 == 1,
+            "{content}"
+        );
+    }
+}
+
+#[test]
+fn test_config_file_defaults() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n127.0.0.1 keep.local\n");
+    let backups = tempfile::tempdir().unwrap();
+    let config = create_test_hosts_file(&format!(
+        "backup_dir = {:?}\n\
+         protected = [\"keep.local\"]\n\
+         default_ip = \"10.0.0.1\"\n\
+         block_begin = \"# >>> managed\"\n\
+         block_end = \"# <<< managed\"\n",
+        backups.path()
+    ));
+    let hostie = |args: &[&str]| {
+        hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+            .env("HOSTIE_CONFIG", config.path())
+            .args(args)
+            .output()
+            .expect("Failed to execute hostie")
+    };
+
+    assert!(hostie(&["add", "app.local"]).status.success());
+    assert!(
+        fs::read_to_string(hosts_file.path())
+            .unwrap()
+            .contains("10.0.0.1 app.local")
+    );
+
+    let output = hostie(&["remove", "127.0.0.1", "keep.local"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Cannot remove protected entry")
+    );
+    // localhost is no longer protected once the list is replaced
+    assert!(
+        hostie(&["remove", "127.0.0.1", "localhost"])
+            .status
+            .success()
+    );
+
+    let manifest = create_test_hosts_file("10.0.0.2 api.local\n");
+    let output = hostie(&[
+        "enforce",
+        "--once",
+        "--manifest",
+        manifest.path().to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let content = fs::read_to_string(hosts_file.path()).unwrap();
+    assert!(content.contains("# >>> managed enforce\n10.0.0.2 api.local\n# <<< managed enforce\n"));
+
+    // A copy of the file as it was before each of the three changes
+    let mut copies: Vec<String> = fs::read_dir(backups.path())
+        .unwrap()
+        .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+    copies.sort();
+    assert_eq!(copies.len(), 3);
+    assert_eq!(copies[0], "127.0.0.1 keep.local\n10.0.0.1 app.local\n");
+}
+
+#[test]
+fn test_backups_leave_other_files_alone() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let backups = tempfile::tempdir().unwrap();
+    fs::write(backups.path().join("hosts.allow"), "ALL: LOCAL\n").unwrap();
+    fs::write(backups.path().join("hosts.orig"), "127.0.0.1 localhost\n").unwrap();
+    let config = create_test_hosts_file(&format!("backup_dir = {:?}\n", backups.path()));
+
+    for i in 0..12 {
+        let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+            .env("HOSTIE_CONFIG", config.path())
+            .args(["add", "127.0.0.1", &format!("app{i}.local")])
+            .output()
+            .expect("Failed to execute hostie");
+        assert!(output.status.success());
+    }
+
+    let mut names: Vec<String> = fs::read_dir(backups.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    // The ten most recent copies, and the files hostie didn't make
+    assert_eq!(names.len(), 12, "{names:?}");
+    assert_eq!(names[10..], ["hosts.allow", "hosts.orig"]);
+    assert_eq!(
+        fs::read_to_string(backups.path().join("hosts.allow")).unwrap(),
+        "ALL: LOCAL\n"
+    );
+}
+
+#[test]
+fn test_invalid_config_file() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let config = create_test_hosts_file("default_ip = \"home\"\n");
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .env("HOSTIE_CONFIG", config.path())
+        .arg("list")
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Invalid config setting"));
+    assert!(stderr.contains("default_ip"));
+}
+
+#[test]
+fn test_hosts_file_flag_with_several_files() {
+    let host = create_test_hosts_file("127.0.0.1 localhost\n");
+    let image = create_test_hosts_file("127.0.0.1 localhost\n127.0.0.1 taken.local\n");
+    let host_path = host.path().to_str().unwrap();
+    let image_path = image.path().to_str().unwrap();
+
+    let output = hostie_command()
+        .args(["--hosts-file", host_path, "--hosts-file", image_path])
+        .args(["add", "10.0.0.5", "api.local"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    for file in [&host, &image] {
+        assert!(
+            fs::read_to_string(file.path())
+                .unwrap()
+                .contains("10.0.0.5 api.local")
+        );
+    }
+
+    // Files where the change fails don't stop the others
+    let output = hostie_command()
+        .args(["--hosts-file", host_path, "--hosts-file", image_path])
+        .args(["add", "taken.local"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains(&format!("{image_path}: Entry already exists"))
+    );
+    assert!(
+        fs::read_to_string(host.path())
+            .unwrap()
+            .contains("127.0.0.1 taken.local")
+    );
+
+    let output = hostie_command()
+        .args([
+            "--hosts-file",
+            host_path,
+            "--hosts-file",
+            image_path,
+            "list",
+        ])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_path_explains_the_hosts_file() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    let hosts_path = hosts_file.path().to_str().unwrap();
+
+    let output = hostie_command_with_hosts_file(hosts_path)
+        .arg("path")
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{hosts_path}\n")
+    );
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("HOSTIE_HOSTS_FILE")
+    );
+
+    // The flag takes precedence over the environment
+    let output = hostie_command_with_hosts_file("/nonexistent/hosts")
+        .args(["--hosts-file", hosts_path, "path"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{hosts_path}\n")
+    );
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("--hosts-file")
+    );
+
+    let config = create_test_hosts_file(&format!("hosts_file = {hosts_path:?}\n"));
+    let output = hostie_command()
+        .env_remove("HOSTIE_HOSTS_FILE")
+        .env("HOSTIE_CONFIG", config.path())
+        .arg("path")
+        .output()
+        .expect("Failed to execute hostie");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{hosts_path}\n")
+    );
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains(&format!("hosts_file in {}", config.path().display()))
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_root_resolves_symlinks_inside_the_root() {
+    use std::os::unix::fs::symlink;
+
+    let root = tempfile::tempdir().unwrap();
+    fs::create_dir_all(root.path().join("etc")).unwrap();
+    fs::create_dir_all(root.path().join("run/systemd")).unwrap();
+    fs::write(
+        root.path().join("run/systemd/hosts"),
+        "127.0.0.1 localhost\n",
+    )
+    .unwrap();
+    // An absolute link in the image points into the image, not the host
+    symlink("/run/systemd/hosts", root.path().join("etc/hosts")).unwrap();
+
+    let output = hostie_command()
+        .args(["--root", root.path().to_str().unwrap()])
+        .args(["add", "10.0.0.5", "api.local"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(root.path().join("run/systemd/hosts")).unwrap(),
+        "127.0.0.1 localhost\n10.0.0.5 api.local\n"
+    );
+
+    // `..` stops at the root
+    fs::remove_file(root.path().join("etc/hosts")).unwrap();
+    symlink(
+        "../../../../../../run/systemd/hosts",
+        root.path().join("etc/hosts"),
+    )
+    .unwrap();
+    let output = hostie_command()
+        .args(["--root", root.path().to_str().unwrap(), "path"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        root.path().join("run/systemd/hosts").to_str().unwrap()
+    );
+}
+
+#[test]
+fn test_netns_hosts_file_is_created_from_the_main_file() {
+    let root = tempfile::tempdir().unwrap();
+    fs::create_dir_all(root.path().join("etc")).unwrap();
+    fs::write(root.path().join("etc/hosts"), "127.0.0.1 localhost\n").unwrap();
+    let hostie = |args: &[&str]| {
+        hostie_command()
+            .args(["--root", root.path().to_str().unwrap(), "--netns", "red"])
+            .args(args)
+            .output()
+            .expect("Failed to execute hostie")
+    };
+
+    // `path` only reports where the file would be
+    let output = hostie(&["path"]);
+    assert!(output.status.success());
+    assert!(!root.path().join("etc/netns").exists());
+
+    let output = hostie(&["add", "10.0.0.1", "peer.local"]);
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Created")
+    );
+    assert_eq!(
+        fs::read_to_string(root.path().join("etc/netns/red/hosts")).unwrap(),
+        "127.0.0.1 localhost\n10.0.0.1 peer.local\n"
+    );
+    assert_eq!(
+        fs::read_to_string(root.path().join("etc/hosts")).unwrap(),
+        "127.0.0.1 localhost\n"
+    );
+
+    let output = hostie(&["remove", "10.0.0.1", "peer.local"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().is_empty());
+
+    let output = hostie_command()
+        .args(["--netns", "../red", "list"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_completions() {
+    let output = hostie_command()
+        .args(["completions", "bash"])
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("complete -o nospace -o bashdefault -F _clap_complete_hostie hostie")
+    );
+
+    let hosts_file = create_test_hosts_file(
+        "127.0.0.1 localhost\n\
+         127.0.0.1 app.local # hostie:tags=dev\n\
+         10.0.0.1 db.local\n\
+         10.0.0.2 a.local b.local\n",
+    );
+    let complete = |args: &[&str]| {
+        let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+            .env("COMPLETE", "fish")
+            .args(["--", "hostie"])
+            .args(args)
+            .output()
+            .expect("Failed to execute hostie");
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // Only entries `remove` can actually remove are offered
+    let ips = complete(&["remove", ""]);
+    assert!(ips.contains("10.0.0.1\tdb.local\n"));
+    assert!(ips.contains("127.0.0.1\tapp.local\n"));
+    assert!(!ips.contains("10.0.0.2"));
+    assert!(!ips.contains("localhost"));
+
+    let hostnames = complete(&["remove", "10.0.0.1", "d"]);
+    assert!(hostnames.starts_with("db.local\t10.0.0.1\n"));
+    // Only the hostnames of the IP address already given
+    let hostnames = complete(&["remove", "10.0.0.1", ""]);
+    assert!(hostnames.contains("db.local\t10.0.0.1\n"));
+    assert!(!hostnames.contains("app.local"));
+
+    assert_eq!(complete(&["remove", "--tag", ""]), "dev\n");
+
+    // From the hosts file the command line being completed works on
+    let other = create_test_hosts_file("10.0.0.9 other.local\n");
+    let other_path = other.path().to_str().unwrap();
+    let ips = complete(&["--hosts-file", other_path, "remove", ""]);
+    assert!(ips.contains("10.0.0.9\tother.local\n"), "{ips}");
+    assert!(!ips.contains("db.local"));
+    let hostnames = complete(&[
+        "remove",
+        &format!("--hosts-file={other_path}"),
+        "10.0.0.9",
+        "",
+    ]);
+    assert!(hostnames.contains("other.local\t10.0.0.9\n"), "{hostnames}");
+
+    let root = tempfile::tempdir().unwrap();
+    fs::create_dir(root.path().join("etc")).unwrap();
+    fs::write(root.path().join("etc/hosts"), "10.0.0.8 image.local\n").unwrap();
+    let ips = complete(&["--root", root.path().to_str().unwrap(), "remove", ""]);
+    assert!(ips.contains("10.0.0.8\timage.local\n"), "{ips}");
+
+    // bash passes the position of the word being completed, which needn't be
+    // the last one
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .env("COMPLETE", "bash")
+        .env("_CLAP_COMPLETE_INDEX", "3")
+        .args([
+            "--",
+            "hostie",
+            "remove",
+            "10.0.0.1",
+            "",
+            "--hosts-file",
+            other_path,
+        ])
+        .output()
+        .expect("Failed to execute hostie");
+    let hostnames = String::from_utf8(output.stdout).unwrap();
+    assert!(hostnames.contains("db.local"), "{hostnames}");
+    assert!(!hostnames.contains("app.local"), "{hostnames}");
+}
+
+#[test]
+fn test_disabled_entries() {
+    use hostie::hosts::{Entry, HostsFile};
+
+    let hosts = HostsFile::parse(
+        "# 127.0.0.1 app.local www.app.local\n\
+         #10.0.0.1 db.local # hostie:tags=dev\n\
+         # The following lines are desirable for IPv6 capable hosts\n\
+         # BEGIN hostie containers\n",
+    );
+    let disabled: Vec<Option<Entry>> = hosts
+        .lines()
+        .iter()
+        .map(|line| line.disabled_entry())
+        .collect();
+
+    let app = disabled[0].as_ref().unwrap();
+    assert_eq!(app.ip, "127.0.0.1");
+    assert_eq!(app.hostnames, ["app.local", "www.app.local"]);
+    assert_eq!(disabled[1].as_ref().unwrap().tags, ["dev"]);
+    assert!(disabled[2].is_none());
+    assert!(disabled[3].is_none());
+
+    // Disabling and enabling again gives back the same line
+    let mut hosts = HostsFile::parse("10.0.0.1 db.local # hostie:tags=dev\n");
+    let line = hosts.entries().next().unwrap().disable();
+    hosts.lines_mut()[0] = line;
+    assert_eq!(hosts.to_string(), "# 10.0.0.1 db.local # hostie:tags=dev\n");
+    let entry = hosts.lines()[0].disabled_entry().unwrap();
+    assert_eq!(entry.to_string(), "10.0.0.1 db.local # hostie:tags=dev");
+}
+
+#[test]
+fn test_tui_needs_a_terminal() {
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .arg("tui")
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("needs a terminal")
+    );
+}
+
+/// A fake editor for `hostie edit`: a shell script run with the file to edit.
+#[cfg(unix)]
+fn create_test_editor(script: &str) -> tempfile::TempPath {
+    use std::os::unix::fs::PermissionsExt;
+
+    let editor = create_test_hosts_file(&format!("#!/bin/sh\n{script}\n"));
+    fs::set_permissions(editor.path(), fs::Permissions::from_mode(0o755)).unwrap();
+    editor.into_temp_path()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_edit_replaces_the_file_a_symlink_points_to() {
+    use std::os::unix::fs::symlink;
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("hosts.real"), "127.0.0.1 localhost\n").unwrap();
+    symlink("hosts.real", dir.path().join("hosts")).unwrap();
+    let editor = create_test_editor(r#"echo "10.0.0.1 api.local" >> "$1""#);
+
+    let output = hostie_command_with_hosts_file(dir.path().join("hosts").to_str().unwrap())
+        .env_remove("VISUAL")
+        .env("EDITOR", &editor)
+        .arg("edit")
+        .stdin(std::process::Stdio::null())
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(output.status.success());
+    assert!(
+        fs::symlink_metadata(dir.path().join("hosts"))
+            .unwrap()
+            .is_symlink()
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("hosts.real")).unwrap(),
+        "127.0.0.1 localhost\n10.0.0.1 api.local\n"
+    );
+    // Nothing left behind next to the file
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_edit_accepts_overlapping_blocklists() {
+    let hosts_file = create_test_hosts_file(
+        "127.0.0.1 localhost\n\
+         # BEGIN hostie blocklist:l1\n\
+         0.0.0.0 a.com b.com\n\
+         # END hostie blocklist:l1\n\
+         # BEGIN hostie blocklist:l2\n\
+         0.0.0.0 a.com\n\
+         # END hostie blocklist:l2\n",
+    );
+    let editor = create_test_editor(r#"echo "10.0.0.1 api.local" >> "$1""#);
+
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .env_remove("VISUAL")
+        .env("EDITOR", &editor)
+        .arg("edit")
+        .stdin(std::process::Stdio::null())
+        .output()
+        .expect("Failed to execute hostie");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().is_empty());
+    assert!(
+        fs::read_to_string(hosts_file.path())
+            .unwrap()
+            .ends_with("10.0.0.1 api.local\n")
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_edit_offers_to_fix_problems() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let hosts_file = create_test_hosts_file("127.0.0.1 localhost\n");
+    // Adds a broken line the first time, and fixes it the second
+    let editor = create_test_editor(
+        r#"if grep -q bad "$1"; then sed -i 's/10.0.0.x/10.0.0.2/' "$1"; else echo "10.0.0.x bad.local" >> "$1"; fi"#,
+    );
+    let edit = |answers: &[u8]| {
+        let mut child = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+            .env_remove("VISUAL")
+            .env("EDITOR", &editor)
+            .arg("edit")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to execute hostie");
+        child.stdin.take().unwrap().write_all(answers).unwrap();
+        child.wait_with_output().unwrap()
+    };
+
+    let output = edit(b"x\n");
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("line 2: not an IP address: 10.0.0.x")
+    );
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n"
+    );
+
+    let output = edit(b"e\n");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(hosts_file.path()).unwrap(),
+        "127.0.0.1 localhost\n10.0.0.2 bad.local\n"
+    );
+
+    // Protected entries may only go with an explicit `Q`
+    let editor = create_test_editor(r#"sed -i '/localhost/d' "$1""#);
+    let output = hostie_command_with_hosts_file(hosts_file.path().to_str().unwrap())
+        .env_remove("VISUAL")
+        .env("EDITOR", &editor)
+        .arg("edit")
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to execute hostie");
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("the protected entry for localhost is gone")
+    );
+    assert!(
+        fs::read_to_string(hosts_file.path())
+            .unwrap()
+            .contains("localhost")
+    );
+}